@@ -1,19 +1,185 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
 
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike,
+};
 use exif::DateTime as ExifDateTime;
 
+use crate::dick_sort::checkpoint::Checkpoint;
+use crate::dick_sort::journal::Journal;
+use crate::progress::{lock, NoopProgressReport, SharedProgressReport, TerminalProgressReport};
 use crate::shell::{PrintLevel, Shell};
-use crate::Cli;
+use crate::{interrupt, Cli, UndoCli};
 
+pub use file_scanner::{parse_filename_pattern, parse_folder_pattern};
+pub use shift::{parse_camera_shifts, parse_shift, CameraShifts, Shift};
+pub use template::{parse_template, Language, Template};
+
+mod checkpoint;
+mod conflict;
 mod file_scanner;
+mod hash;
+mod journal;
 mod process;
+mod shift;
+mod template;
+#[cfg(test)]
+mod test_util;
+mod transfer;
+
+/// How many scanned files may wait for processing, which bounds the memory used for large trees
+const QUEUE_SIZE: usize = 1024;
 
 #[derive(Debug)]
 pub struct CopyImage {
     pub source: PathBuf,
     pub date_time: SortedDayTime,
+    /// Where the date was read from
+    pub date_source: DateSource,
+    /// The tag the date was read from, if the source has tags
+    pub date_tag: Option<DateTag>,
+    /// The values of the exif tags used by the format, by the name used there
+    pub exif: HashMap<String, String>,
+    /// Files like `IMG_0001.xmp` that belong to the source and go wherever it goes
+    pub sidecars: Vec<PathBuf>,
+}
+
+/// A media file found by the scanner
+#[derive(Debug)]
+pub enum ScannedFile {
+    Dated(CopyImage),
+    /// A media file without a plausible date
+    Undated(UndatedFile),
+}
+
+impl ScannedFile {
+    pub fn source(&self) -> &Path {
+        match self {
+            ScannedFile::Dated(image) => &image.source,
+            ScannedFile::Undated(undated) => &undated.source,
+        }
+    }
+
+    pub fn sidecars(&self) -> &[PathBuf] {
+        match self {
+            ScannedFile::Dated(image) => &image.sidecars,
+            ScannedFile::Undated(undated) => &undated.sidecars,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UndatedFile {
+    pub source: PathBuf,
+    /// Why the file has no date, e.g. the dates that were rejected
+    pub reason: String,
+    /// Files like `IMG_0001.xmp` that belong to the source and go wherever it goes
+    pub sidecars: Vec<PathBuf>,
+}
+
+/// What to do when the destination of a file is already taken
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnConflict {
+    /// Leave the source where it is
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Write next to the existing file with a numbered suffix. `compare-content` is still accepted for it, which did
+    /// the same once identical content was detected by every policy.
+    #[clap(alias = "compare-content")]
+    Rename,
+    /// Replace the existing file only if the source was modified later
+    KeepNewer,
+}
+
+/// Where the date of a file can be read from
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum DateSource {
+    /// An XMP sidecar next to the file, e.g. `IMG_0001.xmp` or `IMG_0001.CR2.xmp`
+    Sidecar,
+    /// XMP embedded in the file
+    Xmp,
+    /// The file's own metadata, like exif, PNG text chunks or the creation date of a video
+    Exif,
+    /// A date in the file name, e.g. `IMG-20230105-WA0003.jpg`
+    Filename,
+    /// A date in the names of the folders under the source dir, e.g. `2009-07 Italy/` or `2011/Christmas/`
+    Folder,
+    /// The earlier of the modification and birth time of the file, only used with `--fallback-mtime`
+    #[clap(skip)]
+    FileTime,
+}
+
+/// Which date of a file a tag holds. Exif and XMP name the same dates differently.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum DateTag {
+    /// When the photo was taken, exif `DateTimeOriginal` or XMP `exif:DateTimeOriginal` and `photoshop:DateCreated`
+    Original,
+    /// When the photo was digitized, e.g. scanned, exif `DateTimeDigitized` or XMP `exif:DateTimeDigitized` and
+    /// `xmp:CreateDate`
+    Digitized,
+    /// When the file was last changed, exif `DateTime` or XMP `xmp:ModifyDate`
+    Modified,
+    /// The UTC time of the GPS fix, exif `GPSDateStamp` and `GPSTimeStamp`
+    Gps,
+    /// The creation date of files without exif, e.g. the PNG text `Creation Time` or the movie header of a video
+    Container,
+}
+
+impl std::fmt::Display for DateTag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DateTag::Original => "original date",
+            DateTag::Digitized => "digitized date",
+            DateTag::Modified => "modification date",
+            DateTag::Gps => "GPS date",
+            DateTag::Container => "creation date",
+        };
+        f.write_str(name)
+    }
+}
+
+/// How the date is picked if a source has several
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum DateStrategy {
+    /// The date of the first tag in the tag priority that has one
+    Priority,
+    /// The earliest date
+    Earliest,
+    /// The latest date
+    Latest,
+    /// The original date, otherwise the date of the first tag in the tag priority that has one
+    PreferOriginal,
+}
+
+impl DateSource {
+    /// Sources that only hint at the date of a file, which is mentioned when it is copied or moved
+    pub fn is_guess(self) -> bool {
+        matches!(
+            self,
+            DateSource::Filename | DateSource::Folder | DateSource::FileTime
+        )
+    }
+}
+
+impl std::fmt::Display for DateSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DateSource::Sidecar => "sidecar",
+            DateSource::Xmp => "XMP",
+            DateSource::Exif => "metadata",
+            DateSource::Filename => "file name",
+            DateSource::Folder => "folder",
+            DateSource::FileTime => "file system time",
+        };
+        f.write_str(name)
+    }
 }
 
 #[derive(Debug)]
@@ -29,21 +195,132 @@ pub fn sort(args: Cli, shell: &mut Shell) -> anyhow::Result<()> {
         )
     })?;
 
-    // TODO: A generator pattern would work really nicely here.
-    //       That way the caller could decide whether to collect or to immediately process a file.
-    // dick_sort dir
-    let files = file_scanner::scan(
-        args.source_dir.clone(),
-        shell,
-        args.progress,
-        args.recursive,
-    )
-    .context("File scanning failed.")?;
-
-    process::process(&args, files);
+    let mut checkpoint = match (args.resume, args.dry_run) {
+        (true, true) => Checkpoint::load(&args.destination_dir).context("Could not resume.")?,
+        (true, false) => Checkpoint::resume(&args.destination_dir).context("Could not resume.")?,
+        (false, true) => Checkpoint::disabled(),
+        (false, false) => Checkpoint::create(&args.destination_dir)?,
+    };
+
+    let mut journal = match (args.dry_run, checkpoint.journal()) {
+        (true, _) => Journal::disabled(),
+        (false, Some(path)) => Journal::open(path.to_path_buf()),
+        (false, None) => {
+            Journal::create(&args.destination_dir).context("Could not create journal.")?
+        }
+    };
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .context("Could not create worker threads.")?;
+
+    let progress: SharedProgressReport = Mutex::new(if args.progress {
+        Box::new(TerminalProgressReport::new().context("Failed to create progress report.")?)
+    } else {
+        Box::new(NoopProgressReport)
+    });
+
+    // scan in the background and process every file as soon as it is found
+    let (summary, scanned) = thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_SIZE);
+        let pool = &pool;
+        let scan_args = &args;
+        let scan_progress = &progress;
+        let scanner =
+            scope.spawn(move || file_scanner::scan(scan_args, scan_progress, sender, pool));
+        let summary = process::process(
+            &args,
+            receiver,
+            &mut journal,
+            &mut checkpoint,
+            &progress,
+            pool,
+        );
+        let scanned = scanner.join().expect("The scanner should not panic");
+        (summary, scanned)
+    });
+    lock(&progress).finish()?;
+    print_summary(&args, &summary, shell);
+    if let Some(path) = journal.path() {
+        shell.println(PrintLevel::Normal, || {
+            format!("Undo with: dicksort undo {}", path.display())
+        });
+    }
+
+    if args.dry_run {
+        return scanned.context("File scanning failed.");
+    }
+    if let Some(err) = journal.error() {
+        let path = checkpoint.save(None)?;
+        bail!(
+            "Stopped, as the journal could not be written: {}. Progress was saved to {}, continue with --resume.",
+            err,
+            path.display()
+        );
+    }
+    if interrupt::requested() || scanned.is_err() {
+        let path = checkpoint.save(journal.path())?;
+        scanned.context("File scanning failed.")?;
+        bail!(
+            "Interrupted. Progress was saved to {}, continue with --resume.",
+            path.display()
+        );
+    }
+    Checkpoint::remove(&args.destination_dir)
+}
+
+pub fn undo(args: UndoCli, shell: &mut Shell) -> anyhow::Result<()> {
+    let failed = journal::undo(&args.journal, args.dry_run, shell)?;
+    if failed > 0 {
+        bail!("{} changes could not be reverted", failed);
+    }
     Ok(())
 }
 
+fn print_summary(args: &Cli, summary: &process::Summary, shell: &mut Shell) {
+    let prefix = if args.dry_run { "Dry run: " } else { "" };
+    shell.println(PrintLevel::Normal, || {
+        format!(
+            "{}Copied {}, moved {}, skipped {} already present and {} conflicting files. {} failed.",
+            prefix,
+            summary.copied,
+            summary.moved,
+            summary.already_present,
+            summary.skipped,
+            summary.failed
+        )
+    });
+    if !summary.undated.is_empty() {
+        shell.println(PrintLevel::Normal, || match &args.undated {
+            Some(dir) => format!(
+                "{} files have no plausible date and went to {}:",
+                summary.undated.len(),
+                args.destination_dir.join(dir).display()
+            ),
+            None => format!(
+                "{} files were left alone, as they have no plausible date:",
+                summary.undated.len()
+            ),
+        });
+        for undated in &summary.undated {
+            let relative_source = pathdiff::diff_paths(&undated.source, &args.source_dir)
+                .unwrap_or_else(|| undated.source.clone());
+            shell.println(PrintLevel::Normal, || {
+                format!("{:?}: {}", relative_source, undated.reason)
+            });
+        }
+    }
+    if summary.dated_by_file_time > 0 {
+        shell.println(PrintLevel::Normal, || {
+            format!(
+                "{} files were dated by their file system time only.",
+                summary.dated_by_file_time
+            )
+        });
+    }
+}
+
 fn create_target_dir(args: &Cli, shell: &mut Shell) -> anyhow::Result<()> {
     if args.dry_run || args.destination_dir.exists() {
         return Ok(());
@@ -63,7 +340,10 @@ fn create_target_dir(args: &Cli, shell: &mut Shell) -> anyhow::Result<()> {
     Ok(())
 }
 
-#[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
+/// A point in time as found in the file. Month and day are 0 if only the year, or the year and the month are known.
+/// Points in time are ordered by the moment they describe, a time without an offset is taken as local time of this
+/// machine.
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct SortedDayTime {
     pub year: u16,
     pub month: u8,
@@ -75,6 +355,163 @@ pub struct SortedDayTime {
     pub offset: Option<i16>,
 }
 
+impl SortedDayTime {
+    /// The moment of the point in time. An unknown month or day counts as the first.
+    fn instant(&self) -> Option<DateTime<FixedOffset>> {
+        let date = NaiveDate::from_ymd_opt(
+            i32::from(self.year),
+            u32::from(self.month.max(1)),
+            u32::from(self.day.max(1)),
+        )?;
+        let date_time = date.and_hms_nano_opt(
+            u32::from(self.hour),
+            u32::from(self.minute),
+            u32::from(self.second),
+            self.nanosecond.unwrap_or(0),
+        )?;
+        match self.offset {
+            Some(offset) => FixedOffset::east_opt(i32::from(offset) * 60)?
+                .from_local_datetime(&date_time)
+                .single(),
+            None => Local
+                .from_local_datetime(&date_time)
+                .earliest()
+                .map(|date_time| date_time.fixed_offset()),
+        }
+    }
+
+    /// The same moment in another time zone. Partial dates and dates in the file's own local time are kept as
+    /// they are.
+    pub fn in_timezone(&self, timezone: Timezone) -> SortedDayTime {
+        let instant = match timezone {
+            _ if self.month == 0 || self.day == 0 => None,
+            Timezone::Original => None,
+            Timezone::System => self
+                .instant()
+                .map(|instant| instant.with_timezone(&Local).fixed_offset()),
+            Timezone::Fixed(offset) => self.instant().map(|instant| instant.with_timezone(&offset)),
+        };
+        instant.map_or_else(|| self.clone(), SortedDayTime::from)
+    }
+}
+
+impl std::fmt::Display for SortedDayTime {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl Ord for SortedDayTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // the same moment may be written in different zones, which are told apart to agree with Eq
+        let fields = |date: &Self| {
+            (
+                date.year,
+                date.month,
+                date.day,
+                date.hour,
+                date.minute,
+                date.second,
+                date.nanosecond,
+                date.offset,
+            )
+        };
+        self.instant()
+            .cmp(&other.instant())
+            .then_with(|| fields(self).cmp(&fields(other)))
+    }
+}
+
+impl PartialOrd for SortedDayTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The time zone files are sorted in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Timezone {
+    /// The local time of every file, as written by the camera
+    Original,
+    /// The time zone of this machine
+    System,
+    /// A fixed offset to UTC
+    Fixed(FixedOffset),
+}
+
+/// Parses `local`, `system`, `UTC` or an offset like `+02:00` or `-0530`
+pub fn parse_timezone(value: &str) -> Result<Timezone, String> {
+    match value.to_lowercase().as_str() {
+        "local" => return Ok(Timezone::Original),
+        "system" => return Ok(Timezone::System),
+        "utc" | "z" => return Ok(Timezone::Fixed(FixedOffset::east_opt(0).unwrap())),
+        _ => {}
+    }
+    let invalid = || {
+        format!(
+            "{} is neither local, system, UTC nor an offset like +02:00",
+            value
+        )
+    };
+    let (sign, offset) = match value.split_at_checked(1) {
+        Some(("+", offset)) => (1, offset),
+        Some(("-", offset)) => (-1, offset),
+        _ => return Err(invalid()),
+    };
+    // HH:MM or HHMM, the colon is only allowed between hours and minutes
+    let (hours, minutes) = match offset.as_bytes() {
+        [h1, h2, b':', m1, m2] | [h1, h2, m1, m2] => ([*h1, *h2], [*m1, *m2]),
+        _ => return Err(invalid()),
+    };
+    let number = |digits: [u8; 2]| match digits {
+        [tens @ b'0'..=b'9', ones @ b'0'..=b'9'] => {
+            Ok(i32::from(tens - b'0') * 10 + i32::from(ones - b'0'))
+        }
+        _ => Err(invalid()),
+    };
+    let hours = number(hours)?;
+    let minutes = number(minutes)?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+        .filter(|_| minutes < 60)
+        .map(Timezone::Fixed)
+        .ok_or_else(invalid)
+}
+
+impl From<DateTime<FixedOffset>> for SortedDayTime {
+    fn from(value: DateTime<FixedOffset>) -> Self {
+        Self {
+            year: value.year() as u16,
+            month: value.month() as u8,
+            day: value.day() as u8,
+            hour: value.hour() as u8,
+            minute: value.minute() as u8,
+            second: value.second() as u8,
+            nanosecond: Some(value.nanosecond()).filter(|nanos| *nanos > 0),
+            offset: Some((value.offset().local_minus_utc() / 60) as i16),
+        }
+    }
+}
+
+/// A date and time without an offset, e.g. from a file name
+impl From<NaiveDateTime> for SortedDayTime {
+    fn from(value: NaiveDateTime) -> Self {
+        Self {
+            year: value.year() as u16,
+            month: value.month() as u8,
+            day: value.day() as u8,
+            hour: value.hour() as u8,
+            minute: value.minute() as u8,
+            second: value.second() as u8,
+            nanosecond: Some(value.nanosecond()).filter(|nanos| *nanos > 0),
+            offset: None,
+        }
+    }
+}
+
 impl From<ExifDateTime> for SortedDayTime {
     fn from(value: ExifDateTime) -> Self {
         let ExifDateTime { year , month, day, hour, minute, second, nanosecond, offset } = value;
@@ -85,9 +522,10 @@ impl From<ExifDateTime> for SortedDayTime {
 
 #[cfg(test)]
 mod tests {
+    use clap::ValueEnum;
     use exif::DateTime as ExifDateTime;
 
-    use crate::dick_sort::SortedDayTime;
+    use crate::dick_sort::{parse_timezone, OnConflict, SortedDayTime, Timezone};
 
     #[test]
     fn gt_ge_let_le() {
@@ -102,6 +540,53 @@ mod tests {
         assert!(older <= younger);
     }
 
+    #[test]
+    fn compares_instants() {
+        let mut tokyo = ExifDateTime::from_ascii(b"2016:05:04 09:00:00").expect("should be ok");
+        tokyo.parse_offset(b"+09:00").expect("should be ok");
+        let mut berlin = ExifDateTime::from_ascii(b"2016:05:04 03:00:00").expect("should be ok");
+        berlin.parse_offset(b"+02:00").expect("should be ok");
+
+        // 00:00 UTC is before 01:00 UTC, although 09:00 is after 03:00
+        let tokyo = SortedDayTime::from(tokyo);
+        let berlin = SortedDayTime::from(berlin);
+        assert!(tokyo < berlin);
+
+        let utc = parse_timezone("UTC").expect("should parse");
+        assert_eq!(tokyo.in_timezone(utc).hour, 0);
+        assert_eq!(berlin.in_timezone(utc).hour, 1);
+        assert_eq!(tokyo.in_timezone(Timezone::Original), tokyo);
+        let west = parse_timezone("-05:30").expect("should parse");
+        let in_west = tokyo.in_timezone(west);
+        assert_eq!(
+            (in_west.day, in_west.hour, in_west.minute, in_west.offset),
+            (3, 18, 30, Some(-330))
+        );
+    }
+
+    #[test]
+    fn compare_content_is_rename() {
+        assert_eq!(
+            OnConflict::from_str("compare-content", false),
+            Ok(OnConflict::Rename)
+        );
+    }
+
+    #[test]
+    fn timezones() {
+        assert_eq!(parse_timezone("local"), Ok(Timezone::Original));
+        assert_eq!(parse_timezone("system"), Ok(Timezone::System));
+        assert!(parse_timezone("+0200").is_ok());
+        assert!(parse_timezone("-05:30").is_ok());
+        assert!(parse_timezone("+02:60").is_err());
+        assert!(parse_timezone("+2:300").is_err());
+        assert!(parse_timezone("+230:0").is_err());
+        assert!(parse_timezone("+02:00:").is_err());
+        assert!(parse_timezone("+0200:").is_err());
+        assert!(parse_timezone("+02").is_err());
+        assert!(parse_timezone("Europe/Berlin").is_err());
+    }
+
     #[test]
     fn eq() {
         let a = ExifDateTime::from_ascii(b"2016:05:04 03:02:00").expect("should be ok");
@@ -0,0 +1,161 @@
+use anyhow::{bail, Context};
+use std::collections::HashSet;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::dick_sort::journal;
+
+const FILE_NAME: &str = "dicksort.checkpoint";
+
+/// The state of an interrupted run: the sources that were already handled and the journal the run wrote to. Handled
+/// sources are appended to the file as they go, only those of the previous run are kept in memory to skip them.
+pub struct Checkpoint {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    journal: Option<PathBuf>,
+    processed: HashSet<PathBuf>,
+}
+
+impl Checkpoint {
+    /// Starts a new checkpoint, dropping the one of a previous run
+    pub fn create(destination_dir: &Path) -> anyhow::Result<Self> {
+        Self::remove(destination_dir)?;
+        Ok(Self {
+            path: Some(destination_dir.join(FILE_NAME)),
+            file: None,
+            journal: None,
+            processed: HashSet::new(),
+        })
+    }
+
+    /// Continues the checkpoint of a previous run
+    pub fn resume(destination_dir: &Path) -> anyhow::Result<Self> {
+        let mut checkpoint = Self::load(destination_dir)?;
+        checkpoint.path = Some(destination_dir.join(FILE_NAME));
+        Ok(checkpoint)
+    }
+
+    /// Reads the checkpoint of a previous run without writing to it, e.g. for dry runs
+    pub fn load(destination_dir: &Path) -> anyhow::Result<Self> {
+        let path = destination_dir.join(FILE_NAME);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read checkpoint {}", path.display()))?;
+        Self::parse(&content)
+    }
+
+    /// A checkpoint that writes nothing, e.g. for dry runs
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            file: None,
+            journal: None,
+            processed: HashSet::new(),
+        }
+    }
+
+    /// Records the journal of the run, so a resumed run continues it
+    pub fn save(&mut self, journal: Option<&Path>) -> anyhow::Result<PathBuf> {
+        if let Some(journal) = journal {
+            let line = format!("journal\t{}", journal::escape(journal)?);
+            self.append(&line)?;
+        }
+        self.path.clone().context("The checkpoint is disabled")
+    }
+
+    /// Removes the checkpoint of a previous run, if there is one
+    pub fn remove(destination_dir: &Path) -> anyhow::Result<()> {
+        let path = destination_dir.join(FILE_NAME);
+        if path.exists() {
+            fs::remove_file(&path)
+                .with_context(|| format!("Could not remove checkpoint {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    pub fn journal(&self) -> Option<&Path> {
+        self.journal.as_deref()
+    }
+
+    /// Whether the previous run handled the source. Every source is asked for once, so it is forgotten after.
+    pub fn was_processed(&mut self, source: &Path) -> bool {
+        std::path::absolute(source)
+            .map(|absolute| self.processed.remove(&absolute))
+            .unwrap_or(false)
+    }
+
+    pub fn mark_processed(&mut self, source: &Path) -> anyhow::Result<()> {
+        let line = format!("done\t{}", journal::escape(source)?);
+        self.append(&line)
+    }
+
+    fn append(&mut self, line: &str) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("Could not create checkpoint {}", path.display()))?;
+                self.file.insert(file)
+            }
+        };
+        writeln!(file, "{}", line)
+            .with_context(|| format!("Could not write checkpoint {}", path.display()))
+    }
+
+    fn parse(content: &str) -> anyhow::Result<Self> {
+        let mut checkpoint = Checkpoint::disabled();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            match line.split_once('\t') {
+                Some(("journal", path)) => checkpoint.journal = Some(journal::unescape(path)),
+                Some(("done", path)) => {
+                    checkpoint.processed.insert(journal::unescape(path));
+                }
+                _ => bail!("Invalid checkpoint line: {}", line),
+            }
+        }
+        Ok(checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::dick_sort::checkpoint::Checkpoint;
+    use crate::dick_sort::test_util::test_dir;
+
+    #[test]
+    fn resumes_what_was_appended() {
+        let dir = test_dir("checkpoint");
+        let journal = dir.join("dicksort-1.journal");
+        let mut checkpoint = Checkpoint::create(&dir).unwrap();
+        checkpoint.mark_processed(Path::new("/src/a.jpg")).unwrap();
+        checkpoint
+            .mark_processed(Path::new("/src/b\tc.jpg"))
+            .unwrap();
+        checkpoint.save(Some(&journal)).unwrap();
+
+        let mut resumed = Checkpoint::resume(&dir).unwrap();
+        assert_eq!(resumed.journal(), Some(journal.as_path()));
+        assert!(resumed.was_processed(Path::new("/src/b\tc.jpg")));
+        assert!(!resumed.was_processed(Path::new("/src/d.jpg")));
+
+        // a resumed run keeps what the previous one handled
+        resumed.mark_processed(Path::new("/src/d.jpg")).unwrap();
+        let mut resumed = Checkpoint::resume(&dir).unwrap();
+        assert!(resumed.was_processed(Path::new("/src/a.jpg")));
+        assert!(resumed.was_processed(Path::new("/src/d.jpg")));
+
+        assert!(Checkpoint::create(&dir).unwrap().journal().is_none());
+        assert!(Checkpoint::resume(&dir).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
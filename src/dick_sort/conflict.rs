@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dick_sort::{hash, OnConflict, ReadError};
+
+/// The outcome of checking a destination path before writing to it
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// The destination is free
+    Write(PathBuf),
+    /// The destination is taken and will be replaced
+    Overwrite(PathBuf),
+    /// The destination is taken, so the file goes to this free path instead
+    Rename(PathBuf),
+    /// The destination is taken and the file is left alone
+    Skip(SkipReason),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The policy is to never touch existing files
+    Exists,
+    /// The existing file was modified at the same time or later than the source
+    NotNewer,
+    /// The file at the given path has the same content as the source
+    AlreadyPresent(PathBuf),
+}
+
+pub fn resolve(
+    policy: OnConflict,
+    source: &Path,
+    destination: PathBuf,
+) -> Result<Resolution, ReadError> {
+    if !destination.exists() {
+        return Ok(Resolution::Write(destination));
+    }
+
+    // an earlier run may already have put this file there or next to it
+    if let Some(identical) = find_identical(source, &destination)? {
+        return Ok(Resolution::Skip(SkipReason::AlreadyPresent(identical)));
+    }
+
+    let resolution = match policy {
+        OnConflict::Skip => Resolution::Skip(SkipReason::Exists),
+        OnConflict::Overwrite => Resolution::Overwrite(destination),
+        OnConflict::Rename => Resolution::Rename(free_path(&destination)),
+        OnConflict::KeepNewer => {
+            if modified(source)? > modified(&destination)? {
+                Resolution::Overwrite(destination)
+            } else {
+                Resolution::Skip(SkipReason::NotNewer)
+            }
+        }
+    };
+    Ok(resolution)
+}
+
+/// Looks for a file with the same content as the source at the destination or one of its numbered variants
+fn find_identical(source: &Path, destination: &Path) -> Result<Option<PathBuf>, ReadError> {
+    let existing = std::iter::once(destination.to_path_buf())
+        .chain(numbered(destination))
+        .take_while(|candidate| candidate.exists());
+    for candidate in existing {
+        if same_content(source, &candidate)? {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether both files have the same content, which is only hashed if their lengths match
+pub fn same_content(a: &Path, b: &Path) -> Result<bool, ReadError> {
+    Ok(len(a)? == len(b)? && hash::hash_file(a)? == hash::hash_file(b)?)
+}
+
+/// Finds the first path of the form `<stem>_<n>.<ext>` that does not exist yet
+fn free_path(taken: &Path) -> PathBuf {
+    numbered(taken)
+        .find(|candidate| !candidate.exists())
+        .expect("There should be a free name eventually")
+}
+
+/// All paths of the form `<stem>_<n>.<ext>` for n = 1, 2, ...
+fn numbered(taken: &Path) -> impl Iterator<Item = PathBuf> + '_ {
+    let stem = taken
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let ext = taken
+        .extension()
+        .map(|ext| format!(".{}", ext.to_string_lossy()))
+        .unwrap_or_default();
+
+    (1..).map(move |n| taken.with_file_name(format!("{}_{}{}", stem, n, ext)))
+}
+
+fn modified(path: &Path) -> Result<std::time::SystemTime, ReadError> {
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_err(|err| ReadError {
+            msg: err.to_string(),
+        })
+}
+
+fn len(path: &Path) -> Result<u64, ReadError> {
+    fs::metadata(path)
+        .map(|meta| meta.len())
+        .map_err(|err| ReadError {
+            msg: err.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::dick_sort::conflict::{resolve, Resolution, SkipReason};
+    use crate::dick_sort::test_util::test_dir;
+    use crate::dick_sort::OnConflict;
+
+    #[test]
+    fn free_destination_is_written() {
+        let dir = test_dir("conflict_free");
+        let source = dir.join("a.jpg");
+        fs::write(&source, b"a").unwrap();
+
+        let resolution = resolve(OnConflict::Skip, &source, dir.join("b.jpg")).unwrap();
+        assert_eq!(resolution, Resolution::Write(dir.join("b.jpg")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rename_finds_free_suffix() {
+        let dir = test_dir("conflict_rename");
+        let source = dir.join("a.jpg");
+        fs::write(&source, b"a").unwrap();
+        fs::write(dir.join("b.jpg"), b"b").unwrap();
+        fs::write(dir.join("b_1.jpg"), b"b").unwrap();
+
+        let resolution = resolve(OnConflict::Rename, &source, dir.join("b.jpg")).unwrap();
+        assert_eq!(resolution, Resolution::Rename(dir.join("b_2.jpg")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn identical_is_already_present_for_every_policy() {
+        let dir = test_dir("conflict_identical");
+        let source = dir.join("a.jpg");
+        fs::write(&source, b"same").unwrap();
+        fs::write(dir.join("b.jpg"), b"same").unwrap();
+
+        let resolution = resolve(OnConflict::Overwrite, &source, dir.join("b.jpg")).unwrap();
+        assert_eq!(
+            resolution,
+            Resolution::Skip(SkipReason::AlreadyPresent(dir.join("b.jpg")))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn identical_content_is_already_present() {
+        let dir = test_dir("conflict_compare");
+        let source = dir.join("a.jpg");
+        fs::write(&source, b"same").unwrap();
+        fs::write(dir.join("same.jpg"), b"same").unwrap();
+        fs::write(dir.join("other.jpg"), b"diff").unwrap();
+
+        let same = resolve(OnConflict::Rename, &source, dir.join("same.jpg")).unwrap();
+        assert_eq!(
+            same,
+            Resolution::Skip(SkipReason::AlreadyPresent(dir.join("same.jpg")))
+        );
+        let other = resolve(OnConflict::Rename, &source, dir.join("other.jpg")).unwrap();
+        assert_eq!(other, Resolution::Rename(dir.join("other_1.jpg")));
+
+        fs::write(dir.join("other_1.jpg"), b"same").unwrap();
+        let renamed_before = resolve(OnConflict::Rename, &source, dir.join("other.jpg")).unwrap();
+        assert_eq!(
+            renamed_before,
+            Resolution::Skip(SkipReason::AlreadyPresent(dir.join("other_1.jpg")))
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,172 +1,1202 @@
 use anyhow::{anyhow, Context};
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::fs;
 use std::fs::File;
-use std::path::PathBuf;
+use std::io::{Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
 
+use chrono::{
+    DateTime, Days, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+};
 use exif::{DateTime as ExifDateTime, *};
+use rayon::prelude::*;
+use rayon::ThreadPool;
 
-use crate::dick_sort::{CopyImage, ReadError, SortedDayTime};
-use crate::progress::{NoopProgressReport, ProgressReport, TerminalProgressReport};
-use crate::shell::{PrintLevel, Shell};
+use crate::dick_sort::shift::{Camera, Shift};
+use crate::dick_sort::{
+    CopyImage, DateSource, DateStrategy, DateTag, ReadError, ScannedFile, SortedDayTime,
+    UndatedFile,
+};
+use crate::interrupt;
+use crate::progress::{lock, SharedProgressReport};
+use crate::Cli;
 
+mod bmff;
+mod filename;
+mod folder;
+mod raw;
+mod sidecar;
+#[cfg(test)]
+mod test_util;
+mod video;
+mod web;
+mod xmp;
+
+pub use filename::parse_pattern as parse_filename_pattern;
+pub use folder::parse_pattern as parse_folder_pattern;
+
+/// Walks the source dir and sends every media file as soon as it is read.
+/// Stops early if the receiving side hangs up or the run is interrupted.
 pub fn scan(
-    source_dir: PathBuf,
-    shell: &mut Shell,
-    show_progress: bool,
-    recursive: bool,
-) -> anyhow::Result<VecDeque<CopyImage>> {
+    args: &Cli,
+    progress: &SharedProgressReport,
+    sender: SyncSender<ScannedFile>,
+    pool: &ThreadPool,
+) -> anyhow::Result<()> {
     let mut unprocessed_directories: VecDeque<PathBuf> = VecDeque::new();
-    unprocessed_directories.push_back(source_dir);
+    unprocessed_directories.push_back(args.source_dir.clone());
 
-    let mut files: VecDeque<CopyImage> = VecDeque::new();
+    let mut collected_files = 0;
 
-    let mut progress: Box<dyn ProgressReport> = if show_progress {
-        Box::new(TerminalProgressReport::new().context("Failed to create progress report.")?)
-    } else {
-        Box::new(NoopProgressReport)
-    };
-
-    while !unprocessed_directories.is_empty() {
-        progress.set_remaining_dirs(unprocessed_directories.len() - 1)?;
-        progress.set_collected_files(files.len())?;
+    while !unprocessed_directories.is_empty() && !interrupt::requested() {
+        {
+            let mut progress = lock(progress);
+            progress.set_remaining_dirs(unprocessed_directories.len() - 1)?;
+            progress.set_collected_files(collected_files)?;
+        }
         find_files(
-            &mut files,
+            args,
+            &sender,
+            &mut collected_files,
             &mut unprocessed_directories,
-            shell,
-            progress.as_mut(),
-            recursive,
+            progress,
+            pool,
         )?;
-        std::thread::yield_now();
+        lock(progress).flush()?;
     }
 
-    Ok(files)
+    Ok(())
 }
 
 fn find_files(
-    result: &mut VecDeque<CopyImage>,
+    args: &Cli,
+    sender: &SyncSender<ScannedFile>,
+    collected_files: &mut usize,
     unprocessed_directories: &mut VecDeque<PathBuf>,
-    shell: &mut Shell,
-    progress: &mut dyn ProgressReport,
-    recursive: bool,
+    progress: &SharedProgressReport,
+    pool: &ThreadPool,
 ) -> anyhow::Result<()> {
     // pop next from queue. queue is not empty so it should have an entry
     let dir = unprocessed_directories
         .pop_front()
         .ok_or(anyhow!("No more entries"))?;
 
-    progress.set_current_dir(&dir)?;
-    shell.println(PrintLevel::Verbose, || format!("Processing dir {:?}", dir));
+    lock(progress).set_current_dir(&dir)?;
+    if args.verbose {
+        lock(progress).println(&format!("Processing dir {:?}", dir));
+    }
 
     // read the files of the dir
     let read_dir_result =
         fs::read_dir(&dir).with_context(|| format!("Failed to read directory: {:?}", &dir))?;
 
+    let mut files = Vec::new();
+    let mut sidecar_files = Vec::new();
+    let mut sub_directories = Vec::new();
     for dir_entry_result in read_dir_result {
         let entry = match dir_entry_result {
             Ok(entry) => entry,
             Err(err) => {
-                shell.println(PrintLevel::Verbose, || {
-                    format!("Error entering path: {:?}: {}", dir, err)
-                });
+                if args.verbose {
+                    lock(progress).println(&format!("Error entering path: {:?}: {}", dir, err));
+                }
                 continue;
             }
         };
 
         if entry.path().is_dir() {
             // we have a dir, we ignore it if not recursive is active
-            if recursive {
-                unprocessed_directories.push_back(entry.path());
+            if args.recursive {
+                sub_directories.push(entry.path());
             }
             continue;
         }
 
         let path = entry.path();
-        let Some(ext) = path.extension() else {
-            continue;
-        };
+        if sidecar::has_extension(&path, &args.sidecars) {
+            sidecar_files.push(path);
+        } else {
+            files.push(path);
+        }
+    }
+    // the order of read_dir depends on the file system
+    files.sort();
+    sidecar_files.sort();
+    sub_directories.sort();
+    unprocessed_directories.extend(sub_directories);
 
-        let ext_lower_case = ext.to_ascii_lowercase();
-        if ["jpg", "jpeg", "heic"]
-            .iter()
-            .any(|val| ext_lower_case.eq(val))
-        {
-            // we have a jpegish file, so try to read exif.
+    // sidecars without a file of the same name are sorted like any other file, e.g. a lone THM thumbnail
+    let (mut sidecars_of_files, orphans) = sidecar::assign(&files, sidecar_files);
+    files.extend(orphans);
+    sidecars_of_files.resize_with(files.len(), Vec::new);
+
+    let mut candidates: Vec<Candidate> = files
+        .into_iter()
+        .zip(sidecars_of_files)
+        .filter_map(|(path, sidecars)| {
+            // without a kind, the file is recognized by its content while reading
+            let kind = if args.by_extension {
+                Some(kind_by_extension(&path)?)
+            } else {
+                None
+            };
+            Some(Candidate {
+                path,
+                kind,
+                sidecars,
+            })
+        })
+        .collect();
+    candidates.sort_by(|left, right| left.path.cmp(&right.path));
+
+    // read the exif of a few files per job at once and pass them on in order
+    let total = candidates.len();
+    let mut read = 0;
+    for chunk in candidates.chunks(pool.current_num_threads() * 8) {
+        let images: Vec<_> = pool.install(|| {
+            chunk
+                .par_iter()
+                .map(|candidate| (&candidate.path, read_candidate(args, candidate)))
+                .collect()
+        });
+        read += chunk.len();
+        lock(progress).set_dir_progress(read, total)?;
 
-            progress.set_current_file(&path)?;
-            if let Ok(image) = read_exif(path) {
-                // TODO: Handle error case
-                result.push_back(image);
+        for (path, image) in images {
+            lock(progress).set_current_file(path)?;
+            // files of unknown type are no media files, so they are skipped
+            if let Ok(image) = image {
+                if sender.send(image).is_err() {
+                    // processing stopped, so there is no point in scanning further
+                    unprocessed_directories.clear();
+                    return Ok(());
+                }
+                *collected_files += 1;
             }
         }
     }
     Ok(())
 }
 
-fn read_exif(path: PathBuf) -> Result<CopyImage, ReadError> {
-    // open file or fail
-    let file = File::open(&path).map_err(|err| ReadError {
-        msg: err.to_string(),
-    })?;
-    let mut buf_reader = std::io::BufReader::new(&file);
-    let exif_reader = Reader::new();
-    // read exif or fail
-    let exif = exif_reader
-        .read_from_container(&mut buf_reader)
+/// A file that may be a media file, with the sidecars that belong to it
+struct Candidate {
+    path: PathBuf,
+    /// known from the extension, otherwise recognized by content
+    kind: Option<MediaKind>,
+    sidecars: Vec<PathBuf>,
+}
+
+/// Reads the date of a media file. Media files without a plausible date are undated, other files are skipped.
+fn read_candidate(args: &Cli, candidate: &Candidate) -> Result<ScannedFile, ReadError> {
+    let kind = match candidate.kind {
+        Some(kind) => kind,
+        None => kind_by_content(&candidate.path)?,
+    };
+    match read_media(args, candidate.path.clone(), kind) {
+        Ok(image) => Ok(ScannedFile::Dated(CopyImage {
+            sidecars: candidate.sidecars.clone(),
+            ..image
+        })),
+        Err(err) => Ok(ScannedFile::Undated(UndatedFile {
+            source: candidate.path.clone(),
+            reason: err.msg,
+            sidecars: candidate.sidecars.clone(),
+        })),
+    }
+}
+
+/// How the date of a file is read
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum MediaKind {
+    /// jpegish images with exif
+    Exif,
+    /// TIFF based RAW formats like CR2, NEF, ARW, ORF and DNG
+    TiffRaw,
+    /// Canon CR3, an ISO-BMFF container with the exif in Canon specific boxes
+    Cr3,
+    /// Fujifilm RAF, which embeds a JPEG with the exif
+    Raf,
+    /// PNG with exif, text chunks or XMP
+    Png,
+    /// WebP with exif or XMP chunks
+    WebP,
+    /// AVIF, a HEIF with exif and possibly XMP
+    Avif,
+    /// JPEG XL container with exif or XMP boxes
+    Jxl,
+    /// ISO-BMFF / QuickTime videos
+    Video,
+}
+
+fn kind_by_extension(path: &Path) -> Option<MediaKind> {
+    let ext_lower_case = path.extension()?.to_ascii_lowercase();
+    let is_any = |extensions: &[&str]| extensions.iter().any(|val| ext_lower_case.eq(*val));
+    if is_any(&["jpg", "jpeg", "heic"]) {
+        Some(MediaKind::Exif)
+    } else if is_any(&["cr2", "nef", "arw", "orf", "dng"]) {
+        Some(MediaKind::TiffRaw)
+    } else if is_any(&["cr3"]) {
+        Some(MediaKind::Cr3)
+    } else if is_any(&["raf"]) {
+        Some(MediaKind::Raf)
+    } else if is_any(&["png"]) {
+        Some(MediaKind::Png)
+    } else if is_any(&["webp"]) {
+        Some(MediaKind::WebP)
+    } else if is_any(&["avif"]) {
+        Some(MediaKind::Avif)
+    } else if is_any(&["jxl"]) {
+        Some(MediaKind::Jxl)
+    } else if is_any(&["mp4", "mov", "m4v", "3gp"]) {
+        Some(MediaKind::Video)
+    } else {
+        None
+    }
+}
+
+/// The metadata of a file that may hold its date
+#[derive(Default)]
+pub struct Metadata {
+    pub exifs: Vec<Exif>,
+    /// dates found outside of exif, e.g. in PNG text chunks or the movie header of videos
+    pub dates: Vec<SortedDayTime>,
+    /// dates of embedded XMP
+    pub xmp: Vec<TaggedDate>,
+}
+
+/// A date with the tag it was read from. Dates of file names, folders or the file system have no tag.
+#[derive(Debug, Clone)]
+pub struct TaggedDate {
+    pub date_time: SortedDayTime,
+    pub tag: Option<DateTag>,
+}
+
+impl TaggedDate {
+    fn new(date_time: SortedDayTime, tag: DateTag) -> Self {
+        Self {
+            date_time,
+            tag: Some(tag),
+        }
+    }
+
+    fn untagged(date_time: SortedDayTime) -> Self {
+        Self {
+            date_time,
+            tag: None,
+        }
+    }
+}
+
+impl Metadata {
+    /// All dates of the file's own metadata, which are the date tags of the exifs and the other dates. Dates of the
+    /// camera clock are moved by the shift.
+    fn exif_dates(&self, shift: Shift) -> Vec<TaggedDate> {
+        self.exifs
+            .iter()
+            .flat_map(|exif| read_exif_dates(exif, shift))
+            .chain(
+                self.dates
+                    .iter()
+                    .map(|date| TaggedDate::new(shift.apply(date), DateTag::Container)),
+            )
+            .collect()
+    }
+
+    /// The camera of the first exif that names one
+    fn camera(&self) -> Option<Camera> {
+        self.exifs.iter().find_map(|exif| {
+            let ascii = |tag| ascii_value(primary_field(exif, tag)?);
+            let camera = Camera {
+                make: ascii(Tag::Make),
+                model: ascii(Tag::Model),
+                serial: ascii(Tag::BodySerialNumber),
+            };
+            (camera != Camera::default()).then_some(camera)
+        })
+    }
+
+    /// The values of the tags of the first exif that has them, by their name, e.g. `Model` or `LensModel`. Names are
+    /// compared ignoring case, values are written with their unit, e.g. `1/250 s`.
+    fn exif_values(&self, tags: &[&str]) -> HashMap<String, String> {
+        tags.iter()
+            .filter_map(|name| {
+                let value = self.exifs.iter().find_map(|exif| {
+                    let field = exif.fields().find(|field| {
+                        field.ifd_num == In::PRIMARY
+                            && field.tag.to_string().eq_ignore_ascii_case(name)
+                    })?;
+                    match field.value {
+                        Value::Ascii(_) => ascii_value(field),
+                        _ => Some(field.display_value().with_unit(exif).to_string()),
+                    }
+                })?;
+                Some((name.to_string(), value))
+            })
+            .collect()
+    }
+}
+
+/// The first string of an ASCII field without trailing NULs and spaces, if it is not blank
+fn ascii_value(field: &Field) -> Option<String> {
+    match &field.value {
+        Value::Ascii(text) => {
+            let text = String::from_utf8_lossy(text.first()?);
+            let text = text.trim_end_matches('\0').trim();
+            (!text.is_empty()).then(|| text.to_string())
+        }
+        _ => None,
+    }
+}
+
+impl From<Vec<Exif>> for Metadata {
+    fn from(exifs: Vec<Exif>) -> Self {
+        Self {
+            exifs,
+            ..Self::default()
+        }
+    }
+}
+
+/// Recognizes the kind by the first bytes of the file, falling back to the extension for unknown content
+fn kind_by_content(path: &Path) -> Result<MediaKind, ReadError> {
+    let mut head = Vec::with_capacity(HEAD_SIZE);
+    File::open(path)
+        .and_then(|file| file.take(HEAD_SIZE as u64).read_to_end(&mut head))
         .map_err(|err| ReadError {
             msg: err.to_string(),
         })?;
-    // get date time field or fail
-    let orig = read_and_validate(&exif, Tag::DateTimeOriginal);
-    let digi = read_and_validate(&exif, Tag::DateTimeDigitized);
-    let create = read_and_validate(&exif, Tag::DateTime);
-    let gps = read_and_validate(&exif, Tag::GPSDateStamp);
+    kind_by_magic(&head)
+        .or_else(|| kind_by_extension(path))
+        .ok_or(ReadError {
+            msg: "Unknown file type".to_string(),
+        })
+}
 
-    let selected = [orig, digi, create, gps]
-        .into_iter()
-        .flatten()
-        .reduce(|l, r| if l > r { r } else { l });
+/// Enough bytes for the signatures and the brands of an ISO-BMFF `ftyp` box
+const HEAD_SIZE: usize = 64;
+
+fn kind_by_magic(head: &[u8]) -> Option<MediaKind> {
+    let starts_with_any = |signatures: &[&[u8]]| signatures.iter().any(|sig| head.starts_with(sig));
+    if starts_with_any(&[b"\xff\xd8\xff"]) {
+        Some(MediaKind::Exif)
+    } else if starts_with_any(&[b"\x89PNG\r\n\x1a\n"]) {
+        Some(MediaKind::Png)
+    } else if head.len() >= 12 && &head[0..4] == b"RIFF" && &head[8..12] == b"WEBP" {
+        Some(MediaKind::WebP)
+    } else if starts_with_any(&[b"\0\0\0\x0cJXL \r\n\x87\n", b"\xff\x0a"]) {
+        Some(MediaKind::Jxl)
+    } else if starts_with_any(&[b"FUJIFILMCCD-RAW "]) {
+        Some(MediaKind::Raf)
+    } else if starts_with_any(&[b"II*\0", b"MM\0*", b"IIRO", b"IIRS", b"MMOR"]) {
+        Some(MediaKind::TiffRaw)
+    } else {
+        kind_by_bmff_brand(head)
+    }
+}
+
+/// ISO-BMFF files are told apart by the brands in their `ftyp` box. QuickTime movies may start without one.
+fn kind_by_bmff_brand(head: &[u8]) -> Option<MediaKind> {
+    let box_type = head.get(4..8)?;
+    if box_type != b"ftyp" {
+        let quicktime_atoms: [&[u8]; 4] = [b"moov", b"mdat", b"wide", b"free"];
+        return quicktime_atoms
+            .contains(&box_type)
+            .then_some(MediaKind::Video);
+    }
+    let size = u32::from_be_bytes(head[0..4].try_into().unwrap()) as usize;
+    let ftyp = head.get(8..size.min(head.len()))?;
+    // the major brand, the minor version and the compatible brands
+    let brands: Vec<&[u8]> = ftyp
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .map(|(_, brand)| brand)
+        .collect();
+    let has_brand = |wanted: &[&[u8]]| brands.iter().any(|brand| wanted.contains(brand));
+    let kind = if has_brand(&[b"crx "]) {
+        MediaKind::Cr3
+    } else if brands
+        .first()
+        .is_some_and(|major| major.starts_with(b"avi"))
+    {
+        MediaKind::Avif
+    } else if has_brand(&[b"heic", b"heix", b"mif1", b"msf1"]) {
+        MediaKind::Exif
+    } else if brands.first().is_some_and(|major| is_video_brand(major)) {
+        MediaKind::Video
+    } else {
+        // e.g. M4A audio, which is left to the extension
+        return None;
+    };
+    Some(kind)
+}
 
-    selected.map_or(
-        Err(ReadError {
+/// Major brands of MP4, QuickTime and 3GP movies. Audio like `M4A ` or `M4B ` shares the container, but not the brand.
+fn is_video_brand(brand: &[u8]) -> bool {
+    const VIDEO_BRANDS: [&[u8]; 14] = [
+        b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"qt  ", b"M4V ",
+        b"M4VH", b"M4VP", b"MSNV", b"XAVC",
+    ];
+    VIDEO_BRANDS.contains(&brand) || brand.starts_with(b"3gp") || brand.starts_with(b"3g2")
+}
+
+fn read_media(args: &Cli, path: PathBuf, kind: MediaKind) -> Result<CopyImage, ReadError> {
+    // a broken or missing exif does not matter if a sidecar, the file name or a folder has the date
+    let metadata = read_metadata(&path, kind);
+    let sidecar = xmp::read_sidecar(&path);
+
+    // only dates written by the camera are shifted, sidecars hold dates the user already corrected. The camera shift
+    // wins over the global one, as it is meant for this camera only.
+    let camera = metadata.as_ref().ok().and_then(Metadata::camera);
+    let camera_shift = camera.and_then(|camera| args.camera_shifts.as_ref()?.find(&camera));
+    let shift = camera_shift.or(args.shift).unwrap_or_default();
+    let shifted = |dates: Vec<TaggedDate>| -> Vec<TaggedDate> {
+        dates
+            .into_iter()
+            .map(|date| TaggedDate {
+                date_time: shift.apply(&date.date_time),
+                ..date
+            })
+            .collect()
+    };
+
+    // the file time comes last and only on request, as copying tools often reset it
+    let file_time = args.fallback_mtime.then_some(&DateSource::FileTime);
+    let mut sources = args.date_precedence.iter().chain(file_time);
+    let mut rejected = Vec::new();
+    let selected = sources.find_map(|source| {
+        let dates = match (source, &metadata) {
+            (DateSource::Sidecar, _) => sidecar.clone(),
+            (DateSource::Filename, _) => filename::read_date(&path, &args.filename_patterns)
+                .map(TaggedDate::untagged)
+                .into_iter()
+                .collect(),
+            // folders often only name the year or month, which is validated while reading
+            (DateSource::Folder, _) => {
+                let date =
+                    folder::read_date(&args.source_dir, &path, args.folder_pattern.as_ref())?;
+                if let Err(reason) = check_range(args, &date) {
+                    rejected.push(format!("{} is {}", date, reason));
+                    return None;
+                }
+                return Some((TaggedDate::untagged(date), *source));
+            }
+            (DateSource::FileTime, _) => read_file_time(&path)
+                .map(TaggedDate::untagged)
+                .into_iter()
+                .collect(),
+            (DateSource::Xmp, Ok(metadata)) => shifted(metadata.xmp.clone()),
+            (DateSource::Exif, Ok(metadata)) => metadata.exif_dates(shift),
+            (_, Err(_)) => Vec::new(),
+        };
+        select_date(args, dates, &mut rejected).map(|date| (date, *source))
+    });
+
+    let exif = metadata
+        .as_ref()
+        .map(|metadata| metadata.exif_values(&args.format.exif_tags()))
+        .unwrap_or_default();
+
+    match (selected, metadata) {
+        (Some((date, date_source)), _) => Ok(CopyImage {
+            source: path,
+            date_time: date.date_time.in_timezone(args.timezone),
+            date_source,
+            date_tag: date.tag,
+            exif,
+            sidecars: Vec::new(),
+        }),
+        (None, _) if !rejected.is_empty() => Err(ReadError {
+            msg: format!("No plausible date, {}", rejected.join(", ")),
+        }),
+        (None, Err(err)) => Err(err),
+        (None, Ok(_)) => Err(ReadError {
             msg: "No Date Time in file".to_string(),
         }),
-        |sdt| {
-            Ok(CopyImage {
-                source: path,
-                date_time: sdt,
+    }
+}
+
+/// Picks one of the plausible dates of a source by the strategy. Dates without a tag come after the tagged ones in
+/// the priority, ties are broken by the earlier date. Dates that are not plausible are added to the rejected ones
+/// with the reason.
+fn select_date(
+    args: &Cli,
+    dates: Vec<TaggedDate>,
+    rejected: &mut Vec<String>,
+) -> Option<TaggedDate> {
+    let (strategy, priority) = (args.date_strategy, &args.tag_priority);
+    let dates = dates
+        .into_iter()
+        .filter(|date| match check_date(args, &date.date_time) {
+            Ok(()) => true,
+            Err(reason) => {
+                rejected.push(format!("{} is {}", date.date_time, reason));
+                false
+            }
+        });
+    let rank = |tag: Option<DateTag>| match (strategy, tag) {
+        (DateStrategy::PreferOriginal, Some(DateTag::Original)) => Some(0),
+        (_, Some(tag)) => priority
+            .iter()
+            .position(|wanted| *wanted == tag)
+            .map(|position| position + 1),
+        (_, None) => Some(priority.len() + 1),
+    };
+    match strategy {
+        DateStrategy::Earliest => dates.min_by(|l, r| l.date_time.cmp(&r.date_time)),
+        DateStrategy::Latest => dates.max_by(|l, r| l.date_time.cmp(&r.date_time)),
+        DateStrategy::Priority | DateStrategy::PreferOriginal => dates
+            .filter_map(|date| rank(date.tag).map(|rank| (rank, date)))
+            .min_by(|(l_rank, l), (r_rank, r)| {
+                l_rank.cmp(r_rank).then(l.date_time.cmp(&r.date_time))
             })
-        },
-    )
+            .map(|(_, date)| date),
+    }
 }
 
-fn read_and_validate(exif: &Exif, tag: Tag) -> Option<SortedDayTime> {
-    // parse the given tag from the exif
-    if let Some(field) = exif.get_field(tag, In::PRIMARY) {
-        if let Value::Ascii(ref a) = field.value {
-            // parse ascii as DateTime or fail
-            if let Ok(new_date) = ExifDateTime::from_ascii(&a[0]) {
-                // check if we have a previous value
-                return validate_or(Some(SortedDayTime::from(new_date)), None);
+/// The earlier of the modification and birth time in local time. The birth time is read with statx on Linux and is
+/// missing on file systems that do not record it.
+fn read_file_time(path: &Path) -> Option<SortedDayTime> {
+    let metadata = fs::metadata(path).ok()?;
+    [metadata.modified(), metadata.created()]
+        .into_iter()
+        .flatten()
+        .min()
+        .map(|time| DateTime::<Local>::from(time).fixed_offset().into())
+}
+
+fn read_metadata(path: &Path, kind: MediaKind) -> Result<Metadata, ReadError> {
+    // open file or fail
+    let file = File::open(path).map_err(|err| ReadError {
+        msg: err.to_string(),
+    })?;
+    let mut buf_reader = std::io::BufReader::new(&file);
+    // read exif or fail
+    let metadata = match kind {
+        MediaKind::Video => Metadata {
+            dates: vec![video::read_creation_date(&mut buf_reader)?],
+            ..Metadata::default()
+        },
+        MediaKind::TiffRaw => {
+            let exif = raw::read_tiff(&mut buf_reader)?;
+            Metadata {
+                xmp: embedded_xmp(&exif),
+                ..vec![exif].into()
+            }
+        }
+        MediaKind::Cr3 => raw::read_cr3(&mut buf_reader)?.into(),
+        MediaKind::Raf => vec![raw::read_raf(&mut buf_reader)?].into(),
+        MediaKind::Png => web::read_png(&mut buf_reader)?,
+        MediaKind::WebP => web::read_webp(&mut buf_reader)?,
+        MediaKind::Avif => web::read_avif(&mut buf_reader)?,
+        MediaKind::Jxl => web::read_jxl(&mut buf_reader)?,
+        MediaKind::Exif => {
+            let exif = Reader::new().read_from_container(&mut buf_reader);
+            buf_reader.rewind().map_err(|err| ReadError {
+                msg: err.to_string(),
+            })?;
+            let xmp = xmp::read_jpeg_packet(&mut buf_reader)
+                .map(|packet| xmp::read_dates(&packet))
+                .unwrap_or_default();
+            match exif {
+                Ok(exif) => Metadata {
+                    xmp,
+                    ..vec![exif].into()
+                },
+                Err(_) if !xmp.is_empty() => Metadata {
+                    xmp,
+                    ..Metadata::default()
+                },
+                Err(err) => {
+                    return Err(ReadError {
+                        msg: err.to_string(),
+                    })
+                }
             }
         }
+    };
+    Ok(metadata)
+}
+
+/// TIFF based files may embed an XMP packet in the tag 700 of IFD0
+fn embedded_xmp(exif: &Exif) -> Vec<TaggedDate> {
+    let packet = exif
+        .fields()
+        .find(|field| field.ifd_num == In::PRIMARY && field.tag.number() == XMP_TAG);
+    match packet.map(|field| &field.value) {
+        Some(Value::Byte(packet)) | Some(Value::Undefined(packet, _)) => xmp::read_dates(packet),
+        _ => Vec::new(),
     }
-    None
 }
 
-fn validate_or(
-    new_date: Option<SortedDayTime>,
-    old_date: Option<SortedDayTime>,
-) -> Option<SortedDayTime> {
-    if let Some(dt) = &new_date {
-        if dt.year > 0
-            && dt.day > 0
-            && dt.month > 0
-            && dt.day < 32
-            && dt.month < 13
+const XMP_TAG: u16 = 700;
+
+/// Reads the date tags of an exif. The camera clock runs in local time and the GPS clock in UTC, so if the offset of
+/// the camera is not recorded, it is inferred from the difference of both. The shift corrects the camera clock before,
+/// the GPS clock is right anyway.
+fn read_exif_dates(exif: &Exif, shift: Shift) -> Vec<TaggedDate> {
+    let mut dates: Vec<TaggedDate> = EXIF_DATE_TAGS
+        .into_iter()
+        .filter_map(|(tags, date_tag)| {
+            read_date(exif, tags).map(|date| TaggedDate::new(shift.apply(&date), date_tag))
+        })
+        .collect();
+
+    let gps = read_gps_time(exif);
+    let original = dates
+        .iter()
+        .find(|date| date.tag == Some(DateTag::Original));
+    let recorded = original.and_then(|original| original.date_time.offset);
+    let inferred = match (gps, original) {
+        (Some(gps), Some(original)) if recorded.is_none() => infer_offset(&original.date_time, gps),
+        _ => None,
+    };
+    if let Some(offset) = inferred {
+        for date in dates
+            .iter_mut()
+            .filter(|date| date.date_time.offset.is_none())
         {
-            return new_date;
+            date.date_time.offset = Some(offset);
+        }
+    }
+
+    // the GPS time is shown in the offset of the camera if known, otherwise in local time
+    let offset = recorded.or(inferred);
+    let gps = gps.map(|gps| {
+        let utc = Utc.from_utc_datetime(&gps);
+        match offset.and_then(|offset| FixedOffset::east_opt(i32::from(offset) * 60)) {
+            Some(offset) => utc.with_timezone(&offset),
+            None => utc.with_timezone(&Local).fixed_offset(),
+        }
+    });
+    dates.extend(gps.map(|gps| TaggedDate::new(gps.into(), DateTag::Gps)));
+    dates
+}
+
+/// Combines `GPSDateStamp`, which is ASCII `YYYY:MM:DD`, and `GPSTimeStamp`, which are three rationals for hour,
+/// minute and second, into the UTC time of the GPS fix
+fn read_gps_time(exif: &Exif) -> Option<NaiveDateTime> {
+    let date = match &primary_field(exif, Tag::GPSDateStamp)?.value {
+        Value::Ascii(date) => {
+            let date = String::from_utf8_lossy(date.first()?);
+            NaiveDate::parse_from_str(date.trim_end_matches('\0'), "%Y:%m:%d").ok()?
+        }
+        _ => return None,
+    };
+    let time = match &primary_field(exif, Tag::GPSTimeStamp)?.value {
+        Value::Rational(time) if time.len() == 3 => {
+            let seconds = time[0].to_f64() * 3600.0 + time[1].to_f64() * 60.0 + time[2].to_f64();
+            if !(0.0..86400.0).contains(&seconds) {
+                return None;
+            }
+            let whole = seconds.trunc() as u32;
+            let nanos = (seconds.fract() * 1e9) as u32;
+            NaiveTime::from_num_seconds_from_midnight_opt(whole, nanos)?
+        }
+        _ => return None,
+    };
+    Some(date.and_time(time))
+}
+
+/// Offsets are whole quarter hours, but the fix may be a little older than the photo, so a minute or two of difference
+/// is tolerated
+const OFFSET_TOLERANCE_MINUTES: i64 = 2;
+
+/// The offset of a local time to the UTC time of the same moment in minutes, if it is a plausible offset
+fn infer_offset(local: &SortedDayTime, utc: NaiveDateTime) -> Option<i16> {
+    let local = NaiveDate::from_ymd_opt(
+        i32::from(local.year),
+        u32::from(local.month),
+        u32::from(local.day),
+    )?
+    .and_hms_opt(
+        u32::from(local.hour),
+        u32::from(local.minute),
+        u32::from(local.second),
+    )?;
+    let difference = (local - utc).num_minutes();
+    let offset = (difference as f64 / 15.0).round() as i64 * 15;
+    let plausible =
+        offset.abs() <= 14 * 60 && (difference - offset).abs() <= OFFSET_TOLERANCE_MINUTES;
+    plausible.then_some(offset as i16)
+}
+
+/// The date tags of exif with the tags of their offset to UTC and their fraction of a second
+const EXIF_DATE_TAGS: [([Tag; 3], DateTag); 3] = [
+    (
+        [
+            Tag::DateTimeOriginal,
+            Tag::OffsetTimeOriginal,
+            Tag::SubSecTimeOriginal,
+        ],
+        DateTag::Original,
+    ),
+    (
+        [
+            Tag::DateTimeDigitized,
+            Tag::OffsetTimeDigitized,
+            Tag::SubSecTimeDigitized,
+        ],
+        DateTag::Digitized,
+    ),
+    (
+        [Tag::DateTime, Tag::OffsetTime, Tag::SubSecTime],
+        DateTag::Modified,
+    ),
+];
+
+fn read_date(exif: &Exif, [tag, offset_tag, subsec_tag]: [Tag; 3]) -> Option<SortedDayTime> {
+    let ascii = |tag| match primary_field(exif, tag).map(|field| &field.value) {
+        Some(Value::Ascii(ref a)) => a.first(),
+        _ => None,
+    };
+    // parse the given tag from the exif
+    let mut new_date = ExifDateTime::from_ascii(ascii(tag)?).ok()?;
+    // a broken or blank offset or fraction leaves the date as it is
+    if let Some(offset) = ascii(offset_tag) {
+        let _ = new_date.parse_offset(offset);
+    }
+    if let Some(subsec) = ascii(subsec_tag) {
+        let _ = new_date.parse_subsec(subsec);
+        new_date.nanosecond = new_date.nanosecond.filter(|nanos| *nanos > 0);
+    }
+    Some(SortedDayTime::from(new_date))
+}
+
+/// Gets the field of the primary image. Some RAW formats store exif tags directly in IFD0, where they are not
+/// recognized by their context, so they are matched by number as a fallback.
+fn primary_field(exif: &Exif, tag: Tag) -> Option<&Field> {
+    exif.get_field(tag, In::PRIMARY).or_else(|| {
+        exif.fields()
+            .find(|field| field.ifd_num == In::PRIMARY && field.tag.number() == tag.number())
+    })
+}
+
+/// Checks that the date is a real date in the plausible range and no camera reset date. Returns why it is not.
+fn check_date(args: &Cli, date: &SortedDayTime) -> Result<(), String> {
+    let date_time = NaiveDate::from_ymd_opt(
+        i32::from(date.year),
+        u32::from(date.month),
+        u32::from(date.day),
+    )
+    .and_then(|day| {
+        day.and_hms_opt(
+            u32::from(date.hour),
+            u32::from(date.minute),
+            u32::from(date.second),
+        )
+    })
+    .ok_or_else(|| "not a valid date".to_string())?;
+    check_range(args, date)?;
+    if args.blocked_dates.contains(&date_time) {
+        return Err("a camera reset date".to_string());
+    }
+    Ok(())
+}
+
+/// Checks that the day is in the plausible range. An unknown month or day counts as the first, so a partial date is
+/// in range if it starts in it.
+fn check_range(args: &Cli, date: &SortedDayTime) -> Result<(), String> {
+    let day = NaiveDate::from_ymd_opt(
+        i32::from(date.year),
+        u32::from(date.month.max(1)),
+        u32::from(date.day.max(1)),
+    )
+    .ok_or_else(|| "not a valid date".to_string())?;
+    let not_after = args
+        .not_after
+        .unwrap_or_else(|| Local::now().date_naive() + Days::new(1));
+    if day < args.not_before {
+        return Err(format!("before {}", args.not_before));
+    }
+    if day > not_after {
+        return Err(format!("after {}", not_after));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::fs::File;
+    use std::io::{Cursor, Write};
+    use std::time::{Duration, SystemTime};
+
+    use clap::Parser;
+    use exif::experimental::Writer;
+    use exif::{DateTime as ExifDateTime, Exif, Field, In, Reader, Tag, Value};
+
+    use crate::dick_sort::file_scanner::{
+        kind_by_magic, read_exif_dates, read_gps_time, read_media, select_date, MediaKind,
+        Metadata, TaggedDate,
+    };
+    use crate::dick_sort::shift::Shift;
+    use crate::dick_sort::test_util::test_dir;
+    use crate::dick_sort::{DateSource, DateTag, SortedDayTime};
+    use crate::Cli;
+
+    fn ftyp(brands: &[u8]) -> Vec<u8> {
+        let mut head = ((brands.len() + 8) as u32).to_be_bytes().to_vec();
+        head.extend_from_slice(b"ftyp");
+        head.extend_from_slice(brands);
+        head
+    }
+
+    #[test]
+    fn magic_bytes() {
+        assert_eq!(
+            kind_by_magic(b"\xff\xd8\xff\xe1\0\0Exif"),
+            Some(MediaKind::Exif)
+        );
+        assert_eq!(
+            kind_by_magic(b"II*\0\x10\0\0\0CR"),
+            Some(MediaKind::TiffRaw)
+        );
+        assert_eq!(
+            kind_by_magic(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some(MediaKind::WebP)
+        );
+        assert_eq!(kind_by_magic(b"\0\0\0\x08wide"), Some(MediaKind::Video));
+        assert_eq!(kind_by_magic(b"plain text"), None);
+    }
+
+    #[test]
+    fn bmff_brands() {
+        assert_eq!(
+            kind_by_magic(&ftyp(b"crx \0\0\0\x01crx isom")),
+            Some(MediaKind::Cr3)
+        );
+        assert_eq!(
+            kind_by_magic(&ftyp(b"avif\0\0\0\0avifmif1miaf")),
+            Some(MediaKind::Avif)
+        );
+        assert_eq!(
+            kind_by_magic(&ftyp(b"mif1\0\0\0\0mif1heic")),
+            Some(MediaKind::Exif)
+        );
+        assert_eq!(
+            kind_by_magic(&ftyp(b"qt  \0\0\x02\0qt  ")),
+            Some(MediaKind::Video)
+        );
+        // the minor version is not a brand
+        assert_eq!(
+            kind_by_magic(&ftyp(b"isomheicisom")),
+            Some(MediaKind::Video)
+        );
+        assert_eq!(
+            kind_by_magic(&ftyp(b"3gp5\0\0\x02\x003gp5isom")),
+            Some(MediaKind::Video)
+        );
+        // audio shares the container and the compatible brands of videos
+        assert_eq!(kind_by_magic(&ftyp(b"M4A \0\0\0\0M4A mp42isom")), None);
+        assert_eq!(kind_by_magic(&ftyp(b"M4B \0\0\0\0M4B mp42isom")), None);
+    }
+
+    #[test]
+    fn date_precedence() {
+        let dir = test_dir("scanner");
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&33u32.to_be_bytes());
+        png.extend_from_slice(b"tEXtCreation Time\x002021:03:04 10:11:12\0\0\0\0");
+        fs::write(dir.join("shot.png"), png).unwrap();
+        fs::write(
+            dir.join("shot.png.xmp"),
+            "<x:xmpmeta><exif:DateTimeOriginal>2020-01-02T03:04:05</exif:DateTimeOriginal></x:xmpmeta>",
+        )
+        .unwrap();
+
+        let read_year = |precedence: &str| {
+            let args = Cli::parse_from(["dicksort", "a", "b", "--date-precedence", precedence]);
+            read_media(&args, dir.join("shot.png"), MediaKind::Png)
+                .map(|image| image.date_time.year)
+        };
+        assert_eq!(read_year("sidecar,exif").unwrap(), 2020);
+        assert_eq!(read_year("exif,sidecar").unwrap(), 2021);
+        assert!(read_year("xmp").is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shift_camera_dates_only() {
+        let dir = test_dir("shift");
+        let path = dir.join("IMG-20230105-WA0003.png");
+        // the clock of the camera was set a year too early
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&33u32.to_be_bytes());
+        png.extend_from_slice(b"tEXtCreation Time\x001989:03:04 10:11:12\0\0\0\0");
+        fs::write(&path, png).unwrap();
+        fs::write(
+            dir.join("IMG-20230105-WA0003.xmp"),
+            "<x:xmpmeta><exif:DateTimeOriginal>2020-01-02T03:04:05</exif:DateTimeOriginal></x:xmpmeta>",
+        )
+        .unwrap();
+
+        let read_year = |precedence: &str, shift: &str| {
+            let args = Cli::parse_from([
+                "dicksort",
+                "a",
+                "b",
+                "--date-precedence",
+                precedence,
+                "--shift",
+                shift,
+            ]);
+            read_media(&args, path.clone(), MediaKind::Png).map(|image| image.date_time.year)
+        };
+        // the shifted date is validated, not the one of the camera
+        assert!(read_year("exif", "+0s").is_err());
+        assert_eq!(read_year("exif", "+1y").unwrap(), 1990);
+        assert_eq!(read_year("sidecar", "+1y").unwrap(), 2020);
+        assert_eq!(read_year("filename", "+1y").unwrap(), 2023);
+
+        // the GPS clock is right, the offset is inferred from the shifted camera clock
+        let ascii = |text: &str| Value::Ascii(vec![text.as_bytes().to_vec()]);
+        let rationals = |values: [u32; 3]| {
+            Value::Rational(values.iter().map(|value| (*value, 1).into()).collect())
+        };
+        let tiff = tiff_with(&[
+            (Tag::DateTimeOriginal, ascii("1999:03:04 10:11:12")),
+            (Tag::GPSDateStamp, ascii("2000:03:04")),
+            (Tag::GPSTimeStamp, rationals([9, 11, 12])),
+        ]);
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&(tiff.len() as u32).to_be_bytes());
+        png.extend_from_slice(b"eXIf");
+        png.extend_from_slice(&tiff);
+        png.extend_from_slice(&[0; 4]);
+        let gps_path = dir.join("gps.png");
+        fs::write(&gps_path, png).unwrap();
+        let read_date = |tag: &str| {
+            let args = Cli::parse_from([
+                "dicksort",
+                "a",
+                "b",
+                "--shift",
+                "+1y",
+                "--date-strategy",
+                "priority",
+                "--tag-priority",
+                tag,
+            ]);
+            read_media(&args, gps_path.clone(), MediaKind::Png)
+                .unwrap()
+                .date_time
+        };
+        for tag in ["original", "gps"] {
+            let date = read_date(tag);
+            assert_eq!(
+                (date.year, date.hour, date.offset),
+                (2000, 10, Some(60)),
+                "{} should be 2000-03-04 10:11:12+01:00",
+                tag
+            );
         }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn date_strategies() {
+        let date = |day: u8, tag: Option<DateTag>| TaggedDate {
+            date_time: SortedDayTime {
+                year: 2023,
+                month: 1,
+                day,
+                hour: 0,
+                minute: 0,
+                second: 0,
+                nanosecond: None,
+                offset: None,
+            },
+            tag,
+        };
+        // a broken editor wrote an older modification date
+        let dates = vec![
+            date(3, Some(DateTag::Modified)),
+            date(5, Some(DateTag::Original)),
+            date(7, Some(DateTag::Gps)),
+            date(0, Some(DateTag::Digitized)),
+        ];
+        let args = |strategy| {
+            Cli::parse_from([
+                "dicksort",
+                "a",
+                "b",
+                "--date-strategy",
+                strategy,
+                "--tag-priority",
+                "gps,modified",
+            ])
+        };
+        let selected_day = |strategy| {
+            select_date(&args(strategy), dates.clone(), &mut Vec::new())
+                .map(|date| date.date_time.day)
+        };
+        assert_eq!(selected_day("earliest"), Some(3));
+        assert_eq!(selected_day("latest"), Some(7));
+        assert_eq!(selected_day("priority"), Some(7));
+        assert_eq!(selected_day("prefer-original"), Some(5));
+
+        // tags left out of the priority are ignored, dates without a tag are not
+        let original = vec![date(5, Some(DateTag::Original))];
+        assert!(select_date(&args("priority"), original, &mut Vec::new()).is_none());
+        assert!(select_date(&args("priority"), vec![date(5, None)], &mut Vec::new()).is_some());
+    }
+
+    #[test]
+    fn implausible_dates() {
+        let date = |text: &[u8]| SortedDayTime::from(ExifDateTime::from_ascii(text).unwrap());
+        let args = Cli::parse_from(["dicksort", "a", "b", "--not-after", "2024-12-31"]);
+        let mut rejected = Vec::new();
+        for text in [
+            b"2023:02:31 10:00:00",
+            b"0001:01:01 10:00:00",
+            b"1989:12:31 23:59:59",
+            b"2025:01:01 00:00:00",
+            b"2000:01:01 00:00:00",
+        ] {
+            let dates = vec![TaggedDate::new(date(text), DateTag::Original)];
+            assert!(select_date(&args, dates, &mut rejected).is_none());
+        }
+        assert_eq!(
+            rejected,
+            vec![
+                "2023-02-31 10:00:00 is not a valid date",
+                "0001-01-01 10:00:00 is before 1990-01-01",
+                "1989-12-31 23:59:59 is before 1990-01-01",
+                "2025-01-01 00:00:00 is after 2024-12-31",
+                "2000-01-01 00:00:00 is a camera reset date",
+            ]
+        );
+
+        let reset_second = vec![TaggedDate::new(
+            date(b"2000:01:01 00:00:01"),
+            DateTag::Original,
+        )];
+        assert!(select_date(&args, reset_second, &mut rejected).is_some());
+    }
+
+    fn exif_with(fields: &[(Tag, Value)]) -> Exif {
+        Reader::new().read_raw(tiff_with(fields)).unwrap()
+    }
+
+    fn tiff_with(fields: &[(Tag, Value)]) -> Vec<u8> {
+        let fields: Vec<Field> = fields
+            .iter()
+            .map(|(tag, value)| Field {
+                tag: *tag,
+                ifd_num: In::PRIMARY,
+                value: value.clone(),
+            })
+            .collect();
+        let mut writer = Writer::new();
+        fields.iter().for_each(|field| writer.push_field(field));
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, true).unwrap();
+        tiff.into_inner()
+    }
+
+    #[test]
+    fn gps_time_infers_offset() {
+        let ascii = |text: &str| Value::Ascii(vec![text.as_bytes().to_vec()]);
+        let rationals = |values: [u32; 3]| {
+            Value::Rational(values.iter().map(|value| (*value, 1).into()).collect())
+        };
+        let original = (Tag::DateTimeOriginal, ascii("2023:01:05 14:30:12"));
+        let gps_date = (Tag::GPSDateStamp, ascii("2023:01:05"));
+        // the fix is a minute older than the photo
+        let gps_time = (Tag::GPSTimeStamp, rationals([13, 29, 10]));
+
+        let exif = exif_with(&[original.clone(), gps_date.clone(), gps_time]);
+        assert_eq!(
+            read_gps_time(&exif).map(|time| time.to_string()),
+            Some("2023-01-05 13:29:10".to_string())
+        );
+        let dates = read_exif_dates(&exif, Shift::default());
+        assert_eq!(dates.len(), 2);
+        assert_eq!(dates[0].date_time.offset, Some(60));
+        assert_eq!(dates[1].tag, Some(DateTag::Gps));
+        assert_eq!(
+            (dates[1].date_time.hour, dates[1].date_time.offset),
+            (14, Some(60))
+        );
+
+        // the GPS time is shown in the recorded offset of the camera instead of the local one
+        let recorded = (Tag::OffsetTimeOriginal, ascii("-05:00"));
+        let dates = read_exif_dates(
+            &exif_with(&[
+                original.clone(),
+                recorded,
+                gps_date.clone(),
+                (Tag::GPSTimeStamp, rationals([19, 29, 10])),
+            ]),
+            Shift::default(),
+        );
+        assert_eq!(
+            (dates[1].date_time.hour, dates[1].date_time.offset),
+            (14, Some(-300))
+        );
+
+        // a fix long before the photo tells nothing about the offset
+        let stale = (Tag::GPSTimeStamp, rationals([13, 22, 0]));
+        let dates = read_exif_dates(&exif_with(&[original, gps_date, stale]), Shift::default());
+        assert_eq!(dates[0].date_time.offset, None);
+    }
+
+    #[test]
+    fn exif_values_by_name() {
+        let ascii = |text: &str| Value::Ascii(vec![text.as_bytes().to_vec()]);
+        let metadata = Metadata::from(vec![exif_with(&[
+            (Tag::Model, ascii("Canon EOS 5D Mark IV\0")),
+            (Tag::ExposureTime, Value::Rational(vec![(1, 250).into()])),
+            (Tag::LensModel, ascii("  ")),
+        ])]);
+        let values = metadata.exif_values(&["model", "ExposureTime", "LensModel", "Nothing"]);
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["model"], "Canon EOS 5D Mark IV");
+        assert_eq!(values["ExposureTime"], "1/250 s");
+    }
+
+    #[test]
+    fn offset_and_subsec() {
+        let ascii = |text: &str| Value::Ascii(vec![text.as_bytes().to_vec()]);
+        let exif = exif_with(&[
+            (Tag::DateTimeOriginal, ascii("2023:01:05 14:30:12")),
+            (Tag::OffsetTimeOriginal, ascii("-05:00")),
+            (Tag::SubSecTimeOriginal, ascii("25")),
+            (Tag::DateTime, ascii("2023:01:05 14:30:12")),
+            (Tag::OffsetTime, ascii("   :  ")),
+        ]);
+        let dates = read_exif_dates(&exif, Shift::default());
+        assert_eq!(
+            (dates[0].date_time.offset, dates[0].date_time.nanosecond),
+            (Some(-300), Some(250_000_000))
+        );
+        assert_eq!(
+            (dates[1].date_time.offset, dates[1].date_time.nanosecond),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn fallback_mtime() {
+        let dir = test_dir("mtime");
+        let path = dir.join("scan.jpg");
+        let mut file = File::create(&path).unwrap();
+        file.write_all(b"\xff\xd8\xff\xe0\x00\x04\x00\x00\xff\xd9")
+            .unwrap();
+        // 2009-02-13 23:31:30 UTC
+        file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_234_567_890))
+            .unwrap();
+
+        let read = |args: &[&str]| {
+            let args = Cli::parse_from(["dicksort", "a", "b"].iter().chain(args));
+            read_media(&args, path.clone(), MediaKind::Exif)
+        };
+        assert!(read(&[]).is_err());
+        let image = read(&["--fallback-mtime"]).expect("should be dated by the file time");
+        assert_eq!(image.date_source, DateSource::FileTime);
+        assert_eq!(image.date_time.year, 2009);
+        fs::remove_dir_all(dir).unwrap();
     }
-    old_date
 }
@@ -0,0 +1,249 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::dick_sort::ReadError;
+
+/// Walks the top level boxes and returns the content of the first one with the given type
+pub fn find_box<R: Read + Seek>(
+    reader: &mut R,
+    wanted: &[u8; 4],
+) -> Result<Option<Vec<u8>>, ReadError> {
+    let to_read_error = |err: std::io::Error| ReadError {
+        msg: err.to_string(),
+    };
+    reader.seek(SeekFrom::Start(0)).map_err(to_read_error)?;
+    loop {
+        let mut header = [0u8; 8];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(to_read_error(err)),
+        }
+        let size = u32::from_be_bytes(header[0..4].try_into().unwrap()) as u64;
+        let (content_size, to_end) = match size {
+            0 => (0, true),
+            1 => {
+                let mut large = [0u8; 8];
+                reader.read_exact(&mut large).map_err(to_read_error)?;
+                (u64::from_be_bytes(large).saturating_sub(16), false)
+            }
+            size if size < 8 => {
+                return Err(ReadError {
+                    msg: "Invalid box size".to_string(),
+                })
+            }
+            size => (size - 8, false),
+        };
+
+        if &header[4..8] == wanted {
+            let mut content = Vec::new();
+            if to_end {
+                reader.read_to_end(&mut content).map_err(to_read_error)?;
+            } else {
+                reader
+                    .take(content_size)
+                    .read_to_end(&mut content)
+                    .map_err(to_read_error)?;
+            }
+            return Ok(Some(content));
+        }
+        if to_end {
+            return Ok(None);
+        }
+        let content_size = i64::try_from(content_size).map_err(|_| malformed("box size"))?;
+        reader
+            .seek(SeekFrom::Current(content_size))
+            .map_err(to_read_error)?;
+    }
+}
+
+fn malformed(what: &str) -> ReadError {
+    ReadError {
+        msg: format!("Malformed file: {} out of range", what),
+    }
+}
+
+/// The child boxes of a box content as (type, content). Stops at the first malformed box.
+pub fn children(content: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    let mut rest = content;
+    std::iter::from_fn(move || {
+        if rest.len() < 8 {
+            return None;
+        }
+        let size = u32::from_be_bytes(rest[0..4].try_into().unwrap()) as usize;
+        let box_type: [u8; 4] = rest[4..8].try_into().unwrap();
+        let (header_size, size) = match size {
+            0 => (8, rest.len()),
+            1 if rest.len() >= 16 => (
+                16,
+                usize::try_from(u64::from_be_bytes(rest[8..16].try_into().unwrap())).ok()?,
+            ),
+            size => (8, size),
+        };
+        if size < header_size || size > rest.len() {
+            return None;
+        }
+        let child = &rest[header_size..size];
+        rest = &rest[size..];
+        Some((box_type, child))
+    })
+}
+
+/// Reads an item of a `meta` box content, e.g. the XMP of a HEIF, from the extents its `iloc` box lists for it. The
+/// item is the first one whose item type and content type the predicate accepts.
+pub fn read_item<R: Read + Seek>(
+    reader: &mut R,
+    meta: &[u8],
+    wanted: impl Fn(&[u8], &[u8]) -> bool,
+) -> Result<Option<Vec<u8>>, ReadError> {
+    let to_read_error = |err: std::io::Error| ReadError {
+        msg: err.to_string(),
+    };
+    // meta is a full box, its children follow version and flags
+    let boxes = meta.get(4..).unwrap_or_default();
+    let find = |wanted: &[u8; 4]| {
+        children(boxes)
+            .find(|(box_type, _)| box_type == wanted)
+            .map(|(_, content)| content)
+    };
+    let extents = find(b"iinf")
+        .and_then(|iinf| find_item_id(iinf, wanted))
+        .and_then(|id| item_extents(find(b"iloc")?, id));
+    let Some(extents) = extents else {
+        return Ok(None);
+    };
+
+    let mut content = Vec::new();
+    for (base_offset, offset, length) in extents {
+        let offset = base_offset
+            .checked_add(offset)
+            .ok_or_else(|| malformed("item offset"))?;
+        reader
+            .seek(SeekFrom::Start(offset))
+            .map_err(to_read_error)?;
+        // a length of 0 reaches to the end of the file
+        let length = if length == 0 { u64::MAX } else { length };
+        reader
+            .by_ref()
+            .take(length)
+            .read_to_end(&mut content)
+            .map_err(to_read_error)?;
+    }
+    Ok(Some(content))
+}
+
+/// The id of the first item in an `iinf` box whose item type and content type the predicate accepts. Only the item
+/// info entries of version 2 and 3 are read, which are the only ones with an item type.
+fn find_item_id(iinf: &[u8], wanted: impl Fn(&[u8], &[u8]) -> bool) -> Option<u32> {
+    let entries = match iinf.first()? {
+        0 => iinf.get(6..)?,
+        _ => iinf.get(8..)?,
+    };
+    children(entries)
+        .filter(|(box_type, _)| box_type == b"infe")
+        .find_map(|(_, infe)| {
+            let (id, rest) = match infe.first()? {
+                2 => (
+                    u32::from(u16::from_be_bytes(infe.get(4..6)?.try_into().unwrap())),
+                    infe.get(6..)?,
+                ),
+                3 => (
+                    u32::from_be_bytes(infe.get(4..8)?.try_into().unwrap()),
+                    infe.get(8..)?,
+                ),
+                _ => return None,
+            };
+            // after the protection index, the item type, name and content type follow
+            let item_type = rest.get(2..6)?;
+            let content_type = rest
+                .get(6..)?
+                .split(|byte| *byte == 0)
+                .nth(1)
+                .unwrap_or_default();
+            wanted(item_type, content_type).then_some(id)
+        })
+}
+
+/// The extents of an item in an `iloc` box as (base offset, offset, length). Items stored in an `idat` box are not
+/// supported.
+fn item_extents(iloc: &[u8], id: u32) -> Option<Vec<(u64, u64, u64)>> {
+    let version = *iloc.first()?;
+    let mut rest = iloc.get(4..)?;
+    // big endian numbers of the sizes the box declares
+    let mut next = |size: u8| -> Option<u64> {
+        let (bytes, tail) = rest.split_at_checked(usize::from(size))?;
+        rest = tail;
+        Some(
+            bytes
+                .iter()
+                .fold(0, |value, byte| value << 8 | u64::from(*byte)),
+        )
+    };
+    let sizes = next(1)?;
+    let (offset_size, length_size) = ((sizes >> 4) as u8, (sizes & 0xf) as u8);
+    let sizes = next(1)?;
+    let base_offset_size = (sizes >> 4) as u8;
+    let index_size = if version > 0 { (sizes & 0xf) as u8 } else { 0 };
+    let id_size = if version < 2 { 2 } else { 4 };
+
+    for _ in 0..next(id_size)? {
+        let item_id = next(id_size)?;
+        let construction_method = if version > 0 { next(2)? & 0xf } else { 0 };
+        // data reference index
+        next(2)?;
+        let base_offset = next(base_offset_size)?;
+        let mut extents = Vec::new();
+        for _ in 0..next(2)? {
+            next(index_size)?;
+            let offset = next(offset_size)?;
+            let length = next(length_size)?;
+            extents.push((base_offset, offset, length));
+        }
+        if item_id == u64::from(id) {
+            return (construction_method == 0).then_some(extents);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::dick_sort::file_scanner::bmff::{find_box, read_item};
+    use crate::dick_sort::file_scanner::test_util::bmff_box;
+
+    #[test]
+    fn box_size_out_of_range() {
+        // a large size that does not fit a seek
+        let mut file = 1u32.to_be_bytes().to_vec();
+        file.extend_from_slice(b"free");
+        file.extend_from_slice(&u64::MAX.to_be_bytes());
+        file.extend(bmff_box(b"meta", b""));
+
+        let err = find_box(&mut Cursor::new(file), b"meta").unwrap_err();
+        assert!(err.msg.starts_with("Malformed file"), "{}", err.msg);
+    }
+
+    #[test]
+    fn item_offset_out_of_range() {
+        let mut infe = vec![2, 0, 0, 0, 0, 1, 0, 0];
+        infe.extend_from_slice(b"mime");
+        let mut iinf = vec![0, 0, 0, 0, 0, 1];
+        iinf.extend(bmff_box(b"infe", &infe));
+        // offset, length and base offset of 8 bytes, one item of one extent
+        let mut iloc = vec![0, 0, 0, 0, 0x88, 0x80, 0, 1, 0, 1, 0, 0];
+        iloc.extend_from_slice(&u64::MAX.to_be_bytes());
+        iloc.extend_from_slice(&[0, 1]);
+        iloc.extend_from_slice(&1u64.to_be_bytes());
+        iloc.extend_from_slice(&1u64.to_be_bytes());
+        let mut meta = vec![0, 0, 0, 0];
+        meta.extend(bmff_box(b"iinf", &iinf));
+        meta.extend(bmff_box(b"iloc", &iloc));
+
+        let err = read_item(&mut Cursor::new(Vec::new()), &meta, |item_type, _| {
+            item_type == b"mime"
+        })
+        .unwrap_err();
+        assert!(err.msg.starts_with("Malformed file"), "{}", err.msg);
+    }
+}
@@ -0,0 +1,142 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use regex::{Captures, Regex};
+
+use crate::dick_sort::SortedDayTime;
+
+/// Google Pixel names files after the UTC time of the capture, e.g. `PXL_20230105_143012345.jpg`
+const PIXEL_PATTERN: &str = r"PXL_(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})_(?P<hour>\d{2})(?P<minute>\d{2})(?P<second>\d{2})";
+
+/// A date with an optional time in local time and the usual separators, e.g. `IMG-20230105-WA0003.jpg`,
+/// `Screenshot_2023-01-05-14-30-12.png`, `signal-2023-01-05-143012.jpg` or `Screenshot 2023-01-05 at 14.30.12.png`
+const DATE_PATTERN: &str = r"(?:^|\D)(?P<year>(?:19|20)\d{2})[-_.]?(?P<month>\d{2})[-_.]?(?P<day>\d{2})(?:(?:[-_ T.]|\sat\s)?(?P<hour>\d{2})[-_.:]?(?P<minute>\d{2})[-_.:]?(?P<second>\d{2}))?";
+
+/// Parses a user supplied file name pattern, which needs the named groups `year`, `month` and `day`. The groups
+/// `hour`, `minute` and `second` are optional.
+pub fn parse_pattern(value: &str) -> Result<Regex, String> {
+    parse_with_groups(value, &["year", "month", "day"])
+}
+
+/// Parses the regex and makes sure it has all the named groups
+pub fn parse_with_groups(value: &str, groups: &[&str]) -> Result<Regex, String> {
+    let regex = Regex::new(value).map_err(|err| err.to_string())?;
+    let names: Vec<&str> = regex.capture_names().flatten().collect();
+    match groups.iter().find(|group| !names.contains(group)) {
+        Some(missing) => Err(format!("The pattern has no group named {}", missing)),
+        None => Ok(regex),
+    }
+}
+
+fn built_in_patterns() -> &'static [(Regex, bool)] {
+    static PATTERNS: OnceLock<Vec<(Regex, bool)>> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [(PIXEL_PATTERN, true), (DATE_PATTERN, false)]
+            .iter()
+            .map(|(pattern, utc)| {
+                (
+                    Regex::new(pattern).expect("built-in patterns are valid"),
+                    *utc,
+                )
+            })
+            .collect()
+    })
+}
+
+/// Reads the date from the file name with the given patterns, then with the built-in ones. The first match that is a
+/// valid date wins.
+pub fn read_date(path: &Path, patterns: &[Regex]) -> Option<SortedDayTime> {
+    let name = path.file_name()?.to_string_lossy();
+    let local = patterns.iter().map(|pattern| (pattern, false));
+    let built_in = built_in_patterns()
+        .iter()
+        .map(|(pattern, utc)| (pattern, *utc));
+    local.chain(built_in).find_map(|(pattern, utc)| {
+        pattern
+            .captures_iter(&name)
+            .find_map(|captures| to_date_time(&captures))
+            .map(|date_time| to_sorted(date_time, utc))
+    })
+}
+
+fn to_date_time(captures: &Captures) -> Option<NaiveDateTime> {
+    let number = |name: &str| {
+        captures
+            .name(name)
+            .map(|value| value.as_str().parse::<u32>())
+    };
+    let date = NaiveDate::from_ymd_opt(
+        number("year")?.ok()? as i32,
+        number("month")?.ok()?,
+        number("day")?.ok()?,
+    )?;
+    let time = |name| number(name).unwrap_or(Ok(0)).ok();
+    date.and_hms_opt(time("hour")?, time("minute")?, time("second")?)
+}
+
+fn to_sorted(date_time: NaiveDateTime, utc: bool) -> SortedDayTime {
+    if utc {
+        let utc = Utc.from_utc_datetime(&date_time);
+        return utc.with_timezone(&Local).fixed_offset().into();
+    }
+    date_time.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::dick_sort::file_scanner::filename::{parse_pattern, read_date};
+
+    fn date_of(name: &str) -> Option<(u16, u8, u8, u8, u8, u8)> {
+        read_date(Path::new(name), &[]).map(|date| {
+            (
+                date.year,
+                date.month,
+                date.day,
+                date.hour,
+                date.minute,
+                date.second,
+            )
+        })
+    }
+
+    #[test]
+    fn built_in_patterns() {
+        assert_eq!(
+            date_of("IMG-20230105-WA0003.jpg"),
+            Some((2023, 1, 5, 0, 0, 0))
+        );
+        assert_eq!(
+            date_of("Screenshot_2023-01-05-14-30-12.png"),
+            Some((2023, 1, 5, 14, 30, 12))
+        );
+        assert_eq!(
+            date_of("Screenshot 2023-01-05 at 14.30.12.png"),
+            Some((2023, 1, 5, 14, 30, 12))
+        );
+        assert_eq!(
+            date_of("signal-2023-01-05-143012.jpg"),
+            Some((2023, 1, 5, 14, 30, 12))
+        );
+        assert_eq!(
+            date_of("IMG_20230105_143012.jpg"),
+            Some((2023, 1, 5, 14, 30, 12))
+        );
+        assert!(date_of("PXL_20230105_143012345.jpg").is_some());
+        assert_eq!(date_of("DSC_12345678.jpg"), None);
+        assert_eq!(date_of("IMG_0001.jpg"), None);
+    }
+
+    #[test]
+    fn user_patterns() {
+        assert!(parse_pattern(r"(?P<year>\d{4})").is_err());
+        assert!(parse_pattern(r"(").is_err());
+        let pattern = parse_pattern(r"(?P<day>\d{2})\.(?P<month>\d{2})\.(?P<year>\d{4})")
+            .expect("should be valid");
+
+        let date = read_date(Path::new("Urlaub 05.01.2023.jpg"), &[pattern]).unwrap();
+        assert_eq!((date.year, date.month, date.day), (2023, 1, 5));
+    }
+}
@@ -0,0 +1,105 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+use chrono::{Datelike, NaiveDate};
+use regex::{Captures, Regex};
+
+use crate::dick_sort::file_scanner::filename::parse_with_groups;
+use crate::dick_sort::SortedDayTime;
+
+/// A year, optionally followed by month and day, at the start of a folder name or spread over nested folders, e.g.
+/// `2009-07 Italy`, `2011/Christmas` or `2011/07/24`
+const FOLDER_PATTERN: &str = r"(?:\b|_)(?P<year>(?:19|20)\d{2})(?:[-_. /]?(?P<month>0[1-9]|1[0-2])(?:[-_. /]?(?P<day>0[1-9]|[12]\d|3[01]))?)?(?:\b|_)";
+
+/// Parses a user supplied folder pattern, which needs the named group `year`. The groups `month` and `day` are
+/// optional.
+pub fn parse_pattern(value: &str) -> Result<Regex, String> {
+    parse_with_groups(value, &["year"])
+}
+
+fn built_in_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| Regex::new(FOLDER_PATTERN).expect("the built-in pattern is valid"))
+}
+
+/// Reads the date from the folders between the source dir and the file. The pattern is matched against their
+/// path joined by `/` and the last valid match wins, as deeper folders tend to be more precise.
+pub fn read_date(source_dir: &Path, path: &Path, pattern: Option<&Regex>) -> Option<SortedDayTime> {
+    let folders = path.parent()?.strip_prefix(source_dir).ok()?;
+    let folders: Vec<_> = folders
+        .components()
+        .map(|folder| folder.as_os_str().to_string_lossy())
+        .collect();
+    let folders = folders.join("/");
+
+    pattern
+        .unwrap_or_else(|| built_in_pattern())
+        .captures_iter(&folders)
+        .filter_map(|captures| to_date(&captures))
+        .last()
+}
+
+/// The date of a match with 0 for an unknown month or day
+fn to_date(captures: &Captures) -> Option<SortedDayTime> {
+    let number = |name: &str| {
+        captures
+            .name(name)
+            .map(|value| value.as_str().parse::<u32>().ok())
+    };
+    let year = number("year")??;
+    let (month, day) = match (number("month"), number("day")) {
+        (None, _) => (0, 0),
+        (Some(month), None) => (month.filter(|month| (1..=12).contains(month))?, 0),
+        (Some(month), Some(day)) => {
+            let date = NaiveDate::from_ymd_opt(year as i32, month?, day?)?;
+            (date.month0() + 1, date.day0() + 1)
+        }
+    };
+    Some(SortedDayTime {
+        year: u16::try_from(year).ok()?,
+        month: month as u8,
+        day: day as u8,
+        hour: 0,
+        minute: 0,
+        second: 0,
+        nanosecond: None,
+        offset: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::dick_sort::file_scanner::folder::{parse_pattern, read_date};
+
+    fn date_of(path: &str) -> Option<(u16, u8, u8)> {
+        read_date(Path::new("/src"), &Path::new("/src").join(path), None)
+            .map(|date| (date.year, date.month, date.day))
+    }
+
+    #[test]
+    fn built_in_pattern() {
+        assert_eq!(date_of("2009-07 Italy/scan.jpg"), Some((2009, 7, 0)));
+        assert_eq!(date_of("2011/Christmas/scan.jpg"), Some((2011, 0, 0)));
+        assert_eq!(date_of("2011/07/24/scan.jpg"), Some((2011, 7, 24)));
+        assert_eq!(date_of("2011/2011-07 Italy/scan.jpg"), Some((2011, 7, 0)));
+        assert_eq!(date_of("Scans_1998/scan.jpg"), Some((1998, 0, 0)));
+        assert_eq!(date_of("Album 12345/scan.jpg"), None);
+        assert_eq!(date_of("scan_2011.jpg"), None);
+    }
+
+    #[test]
+    fn user_pattern() {
+        assert!(parse_pattern(r"(?P<month>\d{2})").is_err());
+        let pattern = parse_pattern(r"(?P<month>\d{2})\.(?P<year>\d{4})").expect("should be valid");
+
+        let date = read_date(
+            Path::new("/src"),
+            Path::new("/src/Urlaub 07.2009/scan.jpg"),
+            Some(&pattern),
+        )
+        .map(|date| (date.year, date.month, date.day));
+        assert_eq!(date, Some((2009, 7, 0)));
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use exif::{Exif, Reader};
+
+use crate::dick_sort::file_scanner::bmff::{children, find_box};
+use crate::dick_sort::ReadError;
+
+/// The exif is usually at the start of a TIFF based RAW, so most files don't have to be read completely
+const TIFF_PREFIX_SIZE: u64 = 1024 * 1024;
+
+/// Canon's uuid box in the movie box of a CR3, which contains the exif as TIFF structures
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW ";
+
+fn to_read_error(err: impl ToString) -> ReadError {
+    ReadError {
+        msg: err.to_string(),
+    }
+}
+
+/// Reads the exif of TIFF based RAWs like CR2, NEF, ARW, ORF and DNG
+pub fn read_tiff<R: Read + Seek>(reader: &mut R) -> Result<Exif, ReadError> {
+    let mut buf = Vec::new();
+    reader
+        .by_ref()
+        .take(TIFF_PREFIX_SIZE)
+        .read_to_end(&mut buf)
+        .map_err(to_read_error)?;
+    patch_orf_magic(&mut buf);
+
+    match Reader::new().read_raw(buf.clone()) {
+        Ok(exif) => Ok(exif),
+        // some IFD is beyond the prefix
+        Err(_) if buf.len() as u64 == TIFF_PREFIX_SIZE => {
+            reader.read_to_end(&mut buf).map_err(to_read_error)?;
+            Reader::new().read_raw(buf).map_err(to_read_error)
+        }
+        Err(err) => Err(to_read_error(err)),
+    }
+}
+
+/// Olympus uses its own magic number instead of 42, otherwise an ORF is a regular TIFF
+fn patch_orf_magic(buf: &mut [u8]) {
+    match buf.get(0..4) {
+        Some(b"IIRO") | Some(b"IIRS") => buf[2..4].copy_from_slice(&[0x2a, 0x00]),
+        Some(b"MMOR") => buf[2..4].copy_from_slice(&[0x00, 0x2a]),
+        _ => {}
+    }
+}
+
+/// Reads the exif of a CR3. IFD0 is in the box `CMT1`, the exif IFD in `CMT2`.
+pub fn read_cr3<R: Read + Seek>(reader: &mut R) -> Result<Vec<Exif>, ReadError> {
+    let moov = find_box(reader, b"moov")?.ok_or(ReadError {
+        msg: "No movie box in CR3".to_string(),
+    })?;
+    let canon = children(&moov)
+        .filter(|(box_type, _)| box_type == b"uuid")
+        .find_map(|(_, content)| content.strip_prefix(&CANON_UUID))
+        .ok_or(ReadError {
+            msg: "No Canon metadata in CR3".to_string(),
+        })?;
+
+    let exifs: Vec<Exif> = children(canon)
+        .filter(|(box_type, _)| box_type == b"CMT1" || box_type == b"CMT2")
+        .filter_map(|(_, tiff)| Reader::new().read_raw(tiff.to_vec()).ok())
+        .collect();
+    if exifs.is_empty() {
+        return Err(ReadError {
+            msg: "No exif in CR3".to_string(),
+        });
+    }
+    Ok(exifs)
+}
+
+/// Reads the exif of the JPEG preview embedded in a RAF
+pub fn read_raf<R: Read + Seek>(reader: &mut R) -> Result<Exif, ReadError> {
+    let mut header = [0u8; 92];
+    reader.read_exact(&mut header).map_err(to_read_error)?;
+    if !header.starts_with(RAF_MAGIC) {
+        return Err(ReadError {
+            msg: "Not a RAF file".to_string(),
+        });
+    }
+    let offset = u32::from_be_bytes(header[84..88].try_into().unwrap());
+    let length = u32::from_be_bytes(header[88..92].try_into().unwrap());
+
+    let mut jpeg = Vec::new();
+    reader
+        .seek(SeekFrom::Start(offset as u64))
+        .map_err(to_read_error)?;
+    reader
+        .take(length as u64)
+        .read_to_end(&mut jpeg)
+        .map_err(to_read_error)?;
+    Reader::new()
+        .read_from_container(&mut Cursor::new(jpeg))
+        .map_err(to_read_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use exif::{In, Tag};
+
+    use crate::dick_sort::file_scanner::primary_field;
+    use crate::dick_sort::file_scanner::raw::{read_cr3, read_raf, read_tiff, CANON_UUID};
+    use crate::dick_sort::file_scanner::test_util::{bmff_box, tiff};
+
+    #[test]
+    fn orf_magic() {
+        let mut orf = tiff(0x0132, b"2023:01:05 14:30:12\0");
+        orf[2..4].copy_from_slice(b"RO");
+
+        let exif = read_tiff(&mut Cursor::new(orf)).expect("should read ORF");
+        assert!(exif.get_field(Tag::DateTime, In::PRIMARY).is_some());
+    }
+
+    #[test]
+    fn cr3_exif_boxes() {
+        let mut canon = CANON_UUID.to_vec();
+        canon.extend(bmff_box(b"CMT1", &tiff(0x0132, b"2023:01:05 14:30:12\0")));
+        canon.extend(bmff_box(b"CMT2", &tiff(0x9003, b"2023:01:05 14:30:10\0")));
+        let mut cr3 = bmff_box(b"ftyp", b"crx \0\0\0\x01crx isom");
+        cr3.extend(bmff_box(b"moov", &bmff_box(b"uuid", &canon)));
+
+        let exifs = read_cr3(&mut Cursor::new(cr3)).expect("should read CR3");
+        assert_eq!(exifs.len(), 2);
+        // CMT2 holds exif tags in its IFD0
+        assert!(primary_field(&exifs[1], Tag::DateTimeOriginal).is_some());
+    }
+
+    #[test]
+    fn raf_embedded_jpeg() {
+        let exif = tiff(0x0132, b"2023:01:05 14:30:12\0");
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe1];
+        jpeg.extend_from_slice(&((exif.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend(exif);
+        jpeg.extend_from_slice(&[0xff, 0xd9]);
+
+        let mut raf = b"FUJIFILMCCD-RAW 0201FF383501".to_vec();
+        raf.resize(84, 0);
+        raf.extend_from_slice(&100u32.to_be_bytes());
+        raf.extend_from_slice(&(jpeg.len() as u32).to_be_bytes());
+        raf.resize(100, 0);
+        raf.extend(jpeg);
+
+        let exif = read_raf(&mut Cursor::new(raf)).expect("should read RAF");
+        assert!(exif.get_field(Tag::DateTime, In::PRIMARY).is_some());
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+
+/// Whether the file has one of the extensions, ignoring case and a leading dot
+pub fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension().is_some_and(|ext| {
+        extensions
+            .iter()
+            .map(|wanted| wanted.trim_start_matches('.'))
+            .any(|wanted| !wanted.is_empty() && ext.eq_ignore_ascii_case(wanted))
+    })
+}
+
+/// Assigns every sidecar to the file it belongs to. A sidecar named after the whole file name, e.g.
+/// `IMG_0001.CR2.xmp`, belongs to that file, otherwise it belongs to the first file with the same stem, e.g.
+/// `IMG_0001.xmp`. Names are compared ignoring case.
+/// Returns the sidecars of each file and the sidecars that belong to none.
+pub fn assign(files: &[PathBuf], sidecars: Vec<PathBuf>) -> (Vec<Vec<PathBuf>>, Vec<PathBuf>) {
+    let lower = |name: Option<&OsStr>| name.map(|name| name.to_string_lossy().to_lowercase());
+    let mut by_name = HashMap::new();
+    let mut by_stem = HashMap::new();
+    for (index, file) in files.iter().enumerate() {
+        by_name.entry(lower(file.file_name())).or_insert(index);
+        by_stem.entry(lower(file.file_stem())).or_insert(index);
+    }
+
+    let mut assigned = vec![Vec::new(); files.len()];
+    let mut orphans = Vec::new();
+    for sidecar in sidecars {
+        let base = lower(sidecar.file_stem());
+        match by_name.get(&base).or_else(|| by_stem.get(&base)) {
+            Some(index) => assigned[*index].push(sidecar),
+            None => orphans.push(sidecar),
+        }
+    }
+    (assigned, orphans)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::dick_sort::file_scanner::sidecar::{assign, has_extension};
+
+    #[test]
+    fn extensions_ignore_case_and_dot() {
+        let extensions = vec!["xmp".to_string(), ".AAE".to_string(), String::new()];
+        assert!(has_extension(Path::new("IMG_0001.XMP"), &extensions));
+        assert!(has_extension(Path::new("IMG_0001.aae"), &extensions));
+        assert!(!has_extension(Path::new("IMG_0001.jpg"), &extensions));
+        assert!(!has_extension(Path::new("IMG_0001"), &extensions));
+    }
+
+    #[test]
+    fn full_name_before_stem() {
+        let files = vec![PathBuf::from("IMG_1.CR2"), PathBuf::from("IMG_1.JPG")];
+        let (assigned, orphans) = assign(
+            &files,
+            vec![
+                PathBuf::from("img_1.xmp"),
+                PathBuf::from("IMG_1.JPG.xmp"),
+                PathBuf::from("IMG_2.THM"),
+            ],
+        );
+        assert_eq!(
+            assigned,
+            vec![
+                vec![PathBuf::from("img_1.xmp")],
+                vec![PathBuf::from("IMG_1.JPG.xmp")]
+            ]
+        );
+        assert_eq!(orphans, vec![PathBuf::from("IMG_2.THM")]);
+    }
+}
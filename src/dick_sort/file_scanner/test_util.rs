@@ -0,0 +1,19 @@
+/// A little endian TIFF with a single ASCII entry in IFD0
+pub fn tiff(tag: u16, value: &[u8; 20]) -> Vec<u8> {
+    let mut tiff = b"II\x2a\x00\x08\x00\x00\x00".to_vec();
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&tag.to_le_bytes());
+    tiff.extend_from_slice(&2u16.to_le_bytes());
+    tiff.extend_from_slice(&20u32.to_le_bytes());
+    tiff.extend_from_slice(&26u32.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(value);
+    tiff
+}
+
+pub fn bmff_box(box_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut result = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+    result.extend_from_slice(box_type);
+    result.extend_from_slice(content);
+    result
+}
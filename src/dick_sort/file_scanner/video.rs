@@ -0,0 +1,161 @@
+use chrono::{DateTime, FixedOffset, Local, TimeZone, Utc};
+use std::io::{Read, Seek};
+
+use crate::dick_sort::file_scanner::bmff::{children, find_box};
+use crate::dick_sort::{ReadError, SortedDayTime};
+
+/// Seconds between 1904-01-01, the epoch of ISO-BMFF / QuickTime, and 1970-01-01
+const MAC_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Reads the creation date of an ISO-BMFF / QuickTime container (mp4, mov, 3gp).
+///
+/// Apple's `com.apple.quicktime.creationdate` is preferred as it carries the local time and offset of the recording.
+/// Otherwise the UTC `creation_time` of the movie header (or the first track header) is converted to local time.
+pub fn read_creation_date<R: Read + Seek>(reader: &mut R) -> Result<SortedDayTime, ReadError> {
+    let moov = find_box(reader, b"moov")?.ok_or(ReadError {
+        msg: "No movie box in file".to_string(),
+    })?;
+
+    let mut movie_header = None;
+    let mut track_header = None;
+    let mut apple = None;
+    for (box_type, content) in children(&moov) {
+        match &box_type {
+            b"mvhd" => movie_header = creation_time(content),
+            b"trak" if track_header.is_none() => {
+                track_header = children(content)
+                    .find(|(box_type, _)| box_type == b"tkhd")
+                    .and_then(|(_, tkhd)| creation_time(tkhd));
+            }
+            b"meta" => apple = apple_creation_date(content),
+            _ => {}
+        }
+    }
+
+    apple
+        .or_else(|| movie_header.or(track_header).and_then(utc_to_local))
+        .map(SortedDayTime::from)
+        .ok_or(ReadError {
+            msg: "No creation date in file".to_string(),
+        })
+}
+
+/// The creation_time of a full box laid out like `mvhd` or `tkhd`
+fn creation_time(content: &[u8]) -> Option<i64> {
+    let secs = match content.first()? {
+        0 => u32::from_be_bytes(content.get(4..8)?.try_into().ok()?) as i64,
+        1 => i64::try_from(u64::from_be_bytes(content.get(4..12)?.try_into().ok()?)).ok()?,
+        _ => return None,
+    };
+    // zero means the recording device did not set it
+    (secs > 0).then_some(secs - MAC_EPOCH_OFFSET)
+}
+
+fn utc_to_local(unix_secs: i64) -> Option<DateTime<FixedOffset>> {
+    let utc = Utc.timestamp_opt(unix_secs, 0).single()?;
+    Some(utc.with_timezone(&Local).fixed_offset())
+}
+
+/// Reads `com.apple.quicktime.creationdate` from a QuickTime `meta` box with `keys` and `ilst`
+fn apple_creation_date(meta: &[u8]) -> Option<DateTime<FixedOffset>> {
+    let mut keys = Vec::new();
+    let mut items = None;
+    for (box_type, content) in children(meta) {
+        match &box_type {
+            b"keys" => keys = parse_keys(content)?,
+            b"ilst" => items = Some(content),
+            _ => {}
+        }
+    }
+    // the keys are referenced by their 1-based index
+    let index = keys
+        .iter()
+        .position(|key| key == b"com.apple.quicktime.creationdate")? as u32
+        + 1;
+
+    let (_, item) =
+        children(items?).find(|(box_type, _)| u32::from_be_bytes(*box_type) == index)?;
+    let (_, data) = children(item).find(|(box_type, _)| box_type == b"data")?;
+    // 4 bytes type indicator and 4 bytes locale precede the value
+    let value = std::str::from_utf8(data.get(8..)?).ok()?;
+    DateTime::parse_from_str(value.trim_end_matches('\0'), "%Y-%m-%dT%H:%M:%S%z").ok()
+}
+
+fn parse_keys(content: &[u8]) -> Option<Vec<&[u8]>> {
+    // version and flags, then the entry count
+    let count = u32::from_be_bytes(content.get(4..8)?.try_into().ok()?);
+    let mut rest = content.get(8..)?;
+    let mut keys = Vec::new();
+    for _ in 0..count {
+        let size = u32::from_be_bytes(rest.get(0..4)?.try_into().ok()?) as usize;
+        // the size includes itself and the 4 bytes namespace
+        keys.push(rest.get(8..size)?);
+        rest = rest.get(size..)?;
+    }
+    Some(keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::dick_sort::file_scanner::test_util::bmff_box;
+    use crate::dick_sort::file_scanner::video::{read_creation_date, MAC_EPOCH_OFFSET};
+
+    fn mvhd(unix_secs: i64) -> Vec<u8> {
+        let mut content = vec![0u8; 4];
+        content.extend_from_slice(&((unix_secs + MAC_EPOCH_OFFSET) as u32).to_be_bytes());
+        content.extend_from_slice(&[0u8; 92]);
+        bmff_box(b"mvhd", &content)
+    }
+
+    fn apple_meta(date: &str) -> Vec<u8> {
+        let key = b"com.apple.quicktime.creationdate";
+        let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        keys.extend_from_slice(&((key.len() + 8) as u32).to_be_bytes());
+        keys.extend_from_slice(b"mdta");
+        keys.extend_from_slice(key);
+
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(date.as_bytes());
+        let item = bmff_box(&1u32.to_be_bytes(), &bmff_box(b"data", &data));
+
+        let mut meta = bmff_box(b"keys", &keys);
+        meta.extend(bmff_box(b"ilst", &item));
+        bmff_box(b"meta", &meta)
+    }
+
+    fn movie(moov_children: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = bmff_box(b"ftyp", b"isom\0\0\0\0isom");
+        file.extend(bmff_box(b"mdat", &[1, 2, 3, 4]));
+        file.extend(bmff_box(b"moov", &moov_children.concat()));
+        file
+    }
+
+    #[test]
+    fn apple_creation_date_keeps_local_time() {
+        let file = movie(&[mvhd(1_672_929_012), apple_meta("2023-01-05T14:30:12+0100")]);
+
+        let date = read_creation_date(&mut Cursor::new(file)).expect("should have a date");
+        assert_eq!((date.year, date.month, date.day), (2023, 1, 5));
+        assert_eq!((date.hour, date.minute, date.second), (14, 30, 12));
+        assert_eq!(date.offset, Some(60));
+    }
+
+    #[test]
+    fn movie_header_is_utc() {
+        // 2023-01-05 14:30:12 UTC
+        let file = movie(&[mvhd(1_672_929_012)]);
+
+        let date = read_creation_date(&mut Cursor::new(file)).expect("should have a date");
+        let offset = date.offset.expect("local offset should be set") as i64;
+        let local_minutes = (14 * 60 + 30 + offset).rem_euclid(24 * 60);
+        assert_eq!((date.hour as i64 * 60 + date.minute as i64), local_minutes);
+    }
+
+    #[test]
+    fn unset_creation_time() {
+        let file = movie(&[mvhd(-MAC_EPOCH_OFFSET)]);
+        assert!(read_creation_date(&mut Cursor::new(file)).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::io::{BufRead, Read, Seek, SeekFrom};
+
+use exif::Reader;
+
+use crate::dick_sort::file_scanner::bmff::{find_box, read_item};
+use crate::dick_sort::file_scanner::{xmp, Metadata};
+use crate::dick_sort::{ReadError, SortedDayTime};
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const JXL_SIGNATURE: &[u8] = b"\0\0\0\x0cJXL \r\n\x87\n";
+
+fn to_read_error(err: impl ToString) -> ReadError {
+    ReadError {
+        msg: err.to_string(),
+    }
+}
+
+/// Reads the `eXIf` chunk and the uncompressed text chunks of a PNG, skipping all others. Dates are in the keyword
+/// `Creation Time` or in XMP.
+pub fn read_png<R: Read + Seek>(reader: &mut R) -> Result<Metadata, ReadError> {
+    let mut signature = [0u8; 8];
+    reader.read_exact(&mut signature).map_err(to_read_error)?;
+    if signature != PNG_SIGNATURE {
+        return Err(ReadError {
+            msg: "Not a PNG file".to_string(),
+        });
+    }
+
+    let mut metadata = Metadata::default();
+    let mut header = [0u8; 8];
+    while read_header(reader, &mut header)? {
+        let length = u64::from(u32::from_be_bytes(header[0..4].try_into().unwrap()));
+        match &header[4..8] {
+            b"eXIf" | b"tEXt" | b"iTXt" => {
+                let Some(content) = read_content(reader, length)? else {
+                    break;
+                };
+                match &header[4..8] {
+                    b"eXIf" => metadata.exifs.extend(Reader::new().read_raw(content).ok()),
+                    b"tEXt" => {
+                        if let Some((b"Creation Time", text)) = split_keyword(&content) {
+                            let text = String::from_utf8_lossy(text);
+                            metadata.dates.extend(parse_creation_time(&text));
+                        }
+                    }
+                    _ => read_itxt(&content, &mut metadata),
+                }
+            }
+            b"IEND" => break,
+            _ => skip(reader, length)?,
+        }
+        // the crc
+        skip(reader, 4)?;
+    }
+    Ok(metadata)
+}
+
+/// Reads a chunk header, returns false at the end of the file
+fn read_header<R: Read>(reader: &mut R, header: &mut [u8]) -> Result<bool, ReadError> {
+    match reader.read_exact(header) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(to_read_error(err)),
+    }
+}
+
+/// Reads the content of a chunk, which is None if the file ends before
+fn read_content<R: Read>(reader: &mut R, length: u64) -> Result<Option<Vec<u8>>, ReadError> {
+    let mut content = Vec::new();
+    reader
+        .take(length)
+        .read_to_end(&mut content)
+        .map_err(to_read_error)?;
+    Ok((content.len() as u64 == length).then_some(content))
+}
+
+fn skip<R: Seek>(reader: &mut R, length: u64) -> Result<(), ReadError> {
+    let length = i64::try_from(length)
+        .map_err(|_| to_read_error("Malformed file: chunk size out of range"))?;
+    reader
+        .seek(SeekFrom::Current(length))
+        .map_err(to_read_error)?;
+    Ok(())
+}
+
+fn split_keyword(content: &[u8]) -> Option<(&[u8], &[u8])> {
+    let end = content.iter().position(|byte| *byte == 0)?;
+    Some((&content[..end], &content[end + 1..]))
+}
+
+/// An iTXt is `keyword\0 compressed method language\0 translated keyword\0 text`. Compressed text is skipped.
+fn read_itxt(content: &[u8], metadata: &mut Metadata) {
+    let Some((keyword, rest)) = split_keyword(content) else {
+        return;
+    };
+    let Some((&[0, _], rest)) = rest.split_first_chunk::<2>() else {
+        return;
+    };
+    let Some((_language, rest)) = split_keyword(rest) else {
+        return;
+    };
+    let Some((_translated, text)) = split_keyword(rest) else {
+        return;
+    };
+    match keyword {
+        b"XML:com.adobe.xmp" => metadata.xmp.extend(xmp::read_dates(text)),
+        b"Creation Time" => metadata
+            .dates
+            .extend(parse_creation_time(&String::from_utf8_lossy(text))),
+        _ => {}
+    }
+}
+
+/// The PNG spec recommends RFC 1123 for `Creation Time`, but exif and ISO 8601 style dates are common too
+fn parse_creation_time(text: &str) -> Option<SortedDayTime> {
+    let text = text.trim();
+    if let Ok(date_time) = chrono::DateTime::parse_from_rfc2822(text) {
+        return Some(date_time.into());
+    }
+    if let Ok(date_time) = exif::DateTime::from_ascii(text.as_bytes()) {
+        return Some(date_time.into());
+    }
+    xmp::parse_date(&text.replacen(' ', "T", 1))
+}
+
+/// Reads the `EXIF` and `XMP ` chunks of a WebP, skipping all others
+pub fn read_webp<R: Read + Seek>(reader: &mut R) -> Result<Metadata, ReadError> {
+    let mut header = [0u8; 12];
+    reader.read_exact(&mut header).map_err(to_read_error)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+        return Err(ReadError {
+            msg: "Not a WebP file".to_string(),
+        });
+    }
+
+    let mut metadata = Metadata::default();
+    let mut header = [0u8; 8];
+    while read_header(reader, &mut header)? {
+        let size = u64::from(u32::from_le_bytes(header[4..8].try_into().unwrap()));
+        match &header[0..4] {
+            b"EXIF" | b"XMP " => {
+                let Some(content) = read_content(reader, size)? else {
+                    break;
+                };
+                if &header[0..4] == b"XMP " {
+                    metadata.xmp.extend(xmp::read_dates(&content));
+                } else {
+                    // some writers keep the APP1 header of JPEG
+                    let tiff = content
+                        .strip_prefix(b"Exif\0\0")
+                        .map(<[u8]>::to_vec)
+                        .unwrap_or(content);
+                    metadata.exifs.extend(Reader::new().read_raw(tiff).ok());
+                }
+            }
+            _ => skip(reader, size)?,
+        }
+        // chunks are padded to an even size
+        skip(reader, size % 2)?;
+    }
+    Ok(metadata)
+}
+
+/// Reads the `Exif` and `xml ` boxes of a JPEG XL container. Brotli compressed boxes and bare codestreams, which
+/// cannot hold metadata, are not supported.
+pub fn read_jxl<R: Read + Seek>(reader: &mut R) -> Result<Metadata, ReadError> {
+    let mut signature = [0u8; 12];
+    reader.read_exact(&mut signature).map_err(to_read_error)?;
+    if signature != JXL_SIGNATURE {
+        return Err(ReadError {
+            msg: "Not a JPEG XL container".to_string(),
+        });
+    }
+
+    let mut metadata = Metadata::default();
+    // the Exif box starts with the offset of the TIFF header
+    if let Some(content) = find_box(reader, b"Exif")? {
+        let offset = content
+            .get(0..4)
+            .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()) as usize + 4);
+        if let Some(tiff) = offset.and_then(|offset| content.get(offset..)) {
+            metadata
+                .exifs
+                .extend(Reader::new().read_raw(tiff.to_vec()).ok());
+        }
+    }
+    if let Some(content) = find_box(reader, b"xml ")? {
+        metadata.xmp.extend(xmp::read_dates(&content));
+    }
+    Ok(metadata)
+}
+
+/// Reads the exif of an AVIF and its XMP, which is stored as an item of the meta box
+pub fn read_avif<R: BufRead + Seek>(reader: &mut R) -> Result<Metadata, ReadError> {
+    let mut ftyp = [0u8; 12];
+    reader.read_exact(&mut ftyp).map_err(to_read_error)?;
+    if &ftyp[4..8] != b"ftyp" || !ftyp[8..].starts_with(b"avi") {
+        return Err(ReadError {
+            msg: "Not an AVIF file".to_string(),
+        });
+    }
+
+    let mut metadata = Metadata::default();
+    reader.rewind().map_err(to_read_error)?;
+    metadata
+        .exifs
+        .extend(Reader::new().read_from_container(reader).ok());
+    if let Some(meta) = find_box(reader, b"meta")? {
+        let is_xmp = |item_type: &[u8], content_type: &[u8]| {
+            item_type == b"mime" && content_type == b"application/rdf+xml"
+        };
+        if let Some(packet) = read_item(reader, &meta, is_xmp)? {
+            metadata.xmp.extend(xmp::read_dates(&packet));
+        }
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::dick_sort::file_scanner::test_util;
+    use crate::dick_sort::file_scanner::test_util::bmff_box;
+    use crate::dick_sort::file_scanner::web::{read_avif, read_jxl, read_png, read_webp};
+
+    /// A TIFF with DateTimeOriginal
+    fn tiff() -> Vec<u8> {
+        test_util::tiff(0x9003, b"2023:01:05 14:30:12\0")
+    }
+
+    fn png_chunk(chunk_type: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut chunk = (content.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(chunk_type);
+        chunk.extend_from_slice(content);
+        // the crc is not checked
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn png_text_chunks() {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(
+            b"tEXt",
+            b"Creation Time\0Thu, 05 Jan 2023 14:30:12 +0100",
+        ));
+        png.extend(png_chunk(b"tEXt", b"Software\0Screenshot tool"));
+        png.extend(png_chunk(b"IDAT", &[0; 64]));
+        png.extend(png_chunk(
+            b"iTXt",
+            b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta><rdf:Description xmp:CreateDate=\"2023-01-04\"/></x:xmpmeta>",
+        ));
+        png.extend(png_chunk(b"eXIf", &tiff()));
+        png.extend(png_chunk(b"IEND", b""));
+
+        let metadata = read_png(&mut Cursor::new(png)).expect("should read PNG");
+        assert_eq!(metadata.exifs.len(), 1);
+        assert_eq!(metadata.dates.len(), 1);
+        assert_eq!(metadata.xmp.len(), 1);
+        assert_eq!(
+            (metadata.dates[0].day, metadata.dates[0].offset),
+            (5, Some(60))
+        );
+        assert_eq!(metadata.xmp[0].date_time.day, 4);
+    }
+
+    #[test]
+    fn webp_chunks() {
+        let mut exif = b"Exif\0\0".to_vec();
+        exif.extend(tiff());
+        let mut chunks = b"WEBP".to_vec();
+        for (chunk_type, content) in [(b"VP8X", vec![0u8; 10]), (b"EXIF", exif)] {
+            chunks.extend_from_slice(chunk_type);
+            chunks.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(&content);
+            if content.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        }
+        let mut webp = b"RIFF".to_vec();
+        webp.extend_from_slice(&(chunks.len() as u32).to_le_bytes());
+        webp.extend(chunks);
+
+        let metadata = read_webp(&mut Cursor::new(webp)).expect("should read WebP");
+        assert_eq!(metadata.exifs.len(), 1);
+    }
+
+    #[test]
+    fn jxl_boxes() {
+        let mut exif = 0u32.to_be_bytes().to_vec();
+        exif.extend(tiff());
+        let mut jxl = b"\0\0\0\x0cJXL \r\n\x87\n".to_vec();
+        jxl.extend(bmff_box(b"ftyp", b"jxl \0\0\0\0jxl "));
+        jxl.extend(bmff_box(b"Exif", &exif));
+        jxl.extend(bmff_box(
+            b"xml ",
+            b"<x:xmpmeta><xmp:CreateDate>2023-01-04T10:00:00Z</xmp:CreateDate></x:xmpmeta>",
+        ));
+        jxl.extend(bmff_box(b"jxlc", b"\xff\x0a"));
+
+        let metadata = read_jxl(&mut Cursor::new(jxl)).expect("should read JXL");
+        assert_eq!(metadata.exifs.len(), 1);
+        assert_eq!(metadata.xmp.len(), 1);
+    }
+
+    #[test]
+    fn avif_xmp_item() {
+        let packet: &[u8] =
+            b"<x:xmpmeta><xmp:CreateDate>2023-01-04T10:00:00Z</xmp:CreateDate></x:xmpmeta>";
+        let meta = |offset: u32| {
+            let mut infe = vec![2, 0, 0, 0, 0, 1, 0, 0];
+            infe.extend_from_slice(b"mimeXMP\0application/rdf+xml\0");
+            let mut iinf = vec![0, 0, 0, 0, 0, 1];
+            iinf.extend(bmff_box(b"infe", &infe));
+            // offset and length of 4 bytes, one item of one extent
+            let mut iloc = vec![0, 0, 0, 0, 0x44, 0, 0, 1, 0, 1, 0, 0, 0, 1];
+            iloc.extend_from_slice(&offset.to_be_bytes());
+            iloc.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+            let mut meta = vec![0, 0, 0, 0];
+            meta.extend(bmff_box(b"iinf", &iinf));
+            meta.extend(bmff_box(b"iloc", &iloc));
+            bmff_box(b"meta", &meta)
+        };
+        let mut avif = bmff_box(b"ftyp", b"avif\0\0\0\0avifmif1");
+        let offset = avif.len() + meta(0).len() + 8;
+        avif.extend(meta(offset as u32));
+        avif.extend(bmff_box(b"mdat", packet));
+
+        let metadata = read_avif(&mut Cursor::new(avif)).expect("should read AVIF");
+        assert_eq!(metadata.xmp.len(), 1);
+        assert_eq!(metadata.xmp[0].date_time.day, 4);
+    }
+}
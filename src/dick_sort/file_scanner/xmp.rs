@@ -0,0 +1,223 @@
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::dick_sort::file_scanner::TaggedDate;
+use crate::dick_sort::{DateTag, SortedDayTime};
+
+/// XMP properties that hold the date a photo was taken, created or last changed
+const DATE_PROPERTIES: [(&str, DateTag); 5] = [
+    ("exif:DateTimeOriginal", DateTag::Original),
+    ("photoshop:DateCreated", DateTag::Original),
+    ("exif:DateTimeDigitized", DateTag::Digitized),
+    ("xmp:CreateDate", DateTag::Digitized),
+    ("xmp:ModifyDate", DateTag::Modified),
+];
+
+/// The APP1 segment of a JPEG holding XMP starts with the namespace
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Walks the segments of a JPEG up to the image data and returns the XMP packet
+pub fn read_jpeg_packet<R: Read>(reader: &mut R) -> Option<Vec<u8>> {
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker).ok()?;
+    if marker != [0xff, 0xd8] {
+        return None;
+    }
+    loop {
+        reader.read_exact(&mut marker).ok()?;
+        // start of scan or end of image
+        if marker[0] != 0xff || marker[1] == 0xda || marker[1] == 0xd9 {
+            return None;
+        }
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length).ok()?;
+        let mut segment = vec![0; (u16::from_be_bytes(length) as usize).checked_sub(2)?];
+        reader.read_exact(&mut segment).ok()?;
+        if marker[1] == 0xe1 {
+            if let Some(packet) = segment.strip_prefix(JPEG_XMP_HEADER) {
+                return Some(packet.to_vec());
+            }
+        }
+    }
+}
+
+/// Possible sidecars of a file. darktable appends `.xmp` to the file name, Lightroom replaces the extension.
+fn sidecar_paths(path: &Path) -> Vec<PathBuf> {
+    let Some(file_name) = path.file_name() else {
+        return Vec::new();
+    };
+    let file_name = file_name.to_string_lossy();
+    ["xmp", "XMP"]
+        .iter()
+        .map(|ext| path.with_file_name(format!("{}.{}", file_name, ext)))
+        .chain(["xmp", "XMP"].iter().map(|ext| path.with_extension(ext)))
+        .filter(|sidecar| sidecar != path)
+        .collect()
+}
+
+/// Reads the dates of the first sidecar found next to the file
+pub fn read_sidecar(path: &Path) -> Vec<TaggedDate> {
+    sidecar_paths(path)
+        .into_iter()
+        .find(|sidecar| sidecar.is_file())
+        .and_then(|sidecar| fs::read(sidecar).ok())
+        .map(|xmp| read_dates(&xmp))
+        .unwrap_or_default()
+}
+
+/// Reads every date property of an XMP packet which has at least a full date
+pub fn read_dates(xmp: &[u8]) -> Vec<TaggedDate> {
+    let xmp = String::from_utf8_lossy(xmp);
+    DATE_PROPERTIES
+        .iter()
+        .filter_map(|(property, tag)| {
+            let date_time = parse_date(property_value(&xmp, property)?)?;
+            Some(TaggedDate::new(date_time, *tag))
+        })
+        .collect()
+}
+
+/// The value of a property, written either as attribute `name="value"` or as element `<name>value</name>`
+fn property_value<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = xmp;
+    while let Some(pos) = rest.find(name) {
+        let after = &rest[pos + name.len()..];
+        let preceded_by = rest[..pos].chars().last();
+        match preceded_by {
+            Some('<') => {
+                if let Some(value) = after.strip_prefix('>') {
+                    return value.split('<').next().map(str::trim);
+                }
+            }
+            Some(c) if c.is_whitespace() => {
+                let value = after.trim_start().strip_prefix('=')?.trim_start();
+                let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+                return value[1..].split(quote).next();
+            }
+            _ => {}
+        }
+        rest = after;
+    }
+    None
+}
+
+/// Parses the ISO 8601 subset used by XMP: `YYYY-MM-DD[Thh:mm[:ss[.s+]][TZD]]`.
+/// Dates without a day are ignored, as they cannot be sorted into a day.
+pub fn parse_date(value: &str) -> Option<SortedDayTime> {
+    let number = |part: &str| part.parse::<u32>().ok();
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+    let mut date_parts = date.split('-');
+    let year = number(date_parts.next()?)?;
+    let month = number(date_parts.next()?)?;
+    let day = number(date_parts.next()?)?;
+
+    let offset_start = time.find(['Z', '+', '-']).unwrap_or(time.len());
+    let (time, zone) = time.split_at(offset_start);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time_parts = time.split(':').filter(|part| !part.is_empty());
+    let hour = time_parts.next().map_or(Some(0), number)?;
+    let minute = time_parts.next().map_or(Some(0), number)?;
+    let second = time_parts.next().map_or(Some(0), number)?;
+
+    let nanosecond = if fraction.is_empty() {
+        None
+    } else {
+        let digits: String = fraction
+            .chars()
+            .chain("000000000".chars())
+            .take(9)
+            .collect();
+        Some(number(&digits)?).filter(|nanos| *nanos > 0)
+    };
+    let offset = match zone {
+        "" => None,
+        "Z" => Some(0),
+        zone => {
+            let sign = if zone.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = zone[1..].split_once(':').unwrap_or((&zone[1..], "0"));
+            Some(sign * (number(hours)? * 60 + number(minutes)?) as i16)
+        }
+    };
+
+    Some(SortedDayTime {
+        year: u16::try_from(year).ok()?,
+        month: month as u8,
+        day: day as u8,
+        hour: hour as u8,
+        minute: minute as u8,
+        second: second as u8,
+        nanosecond,
+        offset,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Cursor;
+
+    use crate::dick_sort::file_scanner::xmp::{
+        parse_date, read_dates, read_jpeg_packet, read_sidecar,
+    };
+    use crate::dick_sort::test_util::test_dir;
+    use crate::dick_sort::DateTag;
+
+    #[test]
+    fn attribute_and_element_dates() {
+        let xmp = br#"garbage<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF><rdf:Description
+            xmp:CreateDate="2023-01-05T14:30:12+01:00">
+            <exif:DateTimeOriginal>2023-01-05T14:30:10.5</exif:DateTimeOriginal>
+            </rdf:Description></rdf:RDF></x:xmpmeta>garbage"#;
+        let dates = read_dates(xmp);
+        assert_eq!(dates.len(), 2);
+        assert_eq!(
+            (dates[0].date_time.second, dates[0].date_time.nanosecond),
+            (10, Some(500_000_000))
+        );
+        assert_eq!(
+            (dates[1].date_time.hour, dates[1].date_time.offset),
+            (14, Some(60))
+        );
+        assert_eq!(dates[0].tag, Some(DateTag::Original));
+        assert_eq!(dates[1].tag, Some(DateTag::Digitized));
+    }
+
+    #[test]
+    fn partial_dates() {
+        assert!(parse_date("2023-01").is_none());
+        let day = parse_date("2023-01-05").expect("should parse a day");
+        assert_eq!((day.year, day.month, day.day, day.hour), (2023, 1, 5, 0));
+        let west = parse_date("2023-01-05T22:10-05:30").expect("should parse a zone");
+        assert_eq!((west.minute, west.offset), (10, Some(-330)));
+    }
+
+    #[test]
+    fn jpeg_app1_packet() {
+        let xmp = b"<x:xmpmeta><xmp:CreateDate>2023-01-05</xmp:CreateDate></x:xmpmeta>";
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00, 0x04, 0x00, 0x00, 0xff, 0xe1];
+        jpeg.extend_from_slice(&((xmp.len() + 31) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"http://ns.adobe.com/xap/1.0/\0");
+        jpeg.extend_from_slice(xmp);
+        jpeg.extend_from_slice(&[0xff, 0xda]);
+
+        let packet = read_jpeg_packet(&mut Cursor::new(jpeg)).expect("should find XMP");
+        assert_eq!(packet, xmp);
+    }
+
+    #[test]
+    fn sidecar_next_to_file() {
+        let dir = test_dir("xmp");
+        fs::write(
+            dir.join("IMG_0001.xmp"),
+            r#"<x:xmpmeta><rdf:Description photoshop:DateCreated="2023-01-05T14:30:12"/></x:xmpmeta>"#,
+        )
+        .unwrap();
+
+        assert_eq!(read_sidecar(&dir.join("IMG_0001.CR2")).len(), 1);
+        assert!(read_sidecar(&dir.join("IMG_0002.CR2")).is_empty());
+        // a sidecar is not its own sidecar
+        assert!(read_sidecar(&dir.join("IMG_0001.xmp")).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
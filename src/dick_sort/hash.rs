@@ -0,0 +1,39 @@
+use std::fs::File;
+use std::path::Path;
+
+use crate::dick_sort::ReadError;
+
+pub type Hash = blake3::Hash;
+
+/// Hashes the whole content of the file at the given path
+pub fn hash_file(path: &Path) -> Result<Hash, ReadError> {
+    let mut file = File::open(path).map_err(|err| ReadError {
+        msg: format!("Could not open {:?} for hashing: {}", path, err),
+    })?;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(&mut file).map_err(|err| ReadError {
+        msg: format!("Could not hash {:?}: {}", path, err),
+    })?;
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::dick_sort::hash::hash_file;
+    use crate::dick_sort::test_util::test_dir;
+
+    #[test]
+    fn equal_content_equal_hash() {
+        let dir = test_dir("hash");
+        fs::write(dir.join("a"), b"content").unwrap();
+        fs::write(dir.join("b"), b"content").unwrap();
+        fs::write(dir.join("c"), b"other").unwrap();
+
+        let a = hash_file(&dir.join("a")).unwrap();
+        assert_eq!(a, hash_file(&dir.join("b")).unwrap());
+        assert_ne!(a, hash_file(&dir.join("c")).unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+use anyhow::{anyhow, bail, Context};
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::dick_sort::transfer::Moved;
+use crate::dick_sort::{transfer, ReadError};
+use crate::shell::{PrintLevel, Shell};
+
+/// A single file system change made while sorting
+#[derive(Debug, PartialEq, Eq)]
+pub enum Entry {
+    /// A file was copied. `replaced` tells whether an existing file was overwritten.
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        replaced: bool,
+    },
+    /// A file was moved. `replaced` tells whether an existing file was overwritten.
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+        replaced: bool,
+    },
+    CreateDir(PathBuf),
+    RemoveDir(PathBuf),
+}
+
+/// Appends every change of a run to a file in the destination dir, one line per entry. The file is only created with
+/// the first change, so runs that change nothing leave no journal behind. Once a change could not be written, every
+/// further one fails too, as the journal would miss it.
+pub struct Journal {
+    path: Option<PathBuf>,
+    file: Option<File>,
+    error: Option<String>,
+}
+
+impl Journal {
+    pub fn create(destination_dir: &Path) -> anyhow::Result<Self> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let dir = std::path::absolute(destination_dir)?;
+        // runs started within the same second must not share a journal
+        let path = std::iter::once(format!("dicksort-{}.journal", secs))
+            .chain((1..).map(|n| format!("dicksort-{}_{}.journal", secs, n)))
+            .map(|name| dir.join(name))
+            .find(|path| !path.exists())
+            .expect("There should be a free name eventually");
+        Ok(Self::open(path))
+    }
+
+    /// Appends to an existing journal, e.g. when resuming a run
+    pub fn open(path: PathBuf) -> Self {
+        Self {
+            path: Some(path),
+            file: None,
+            error: None,
+        }
+    }
+
+    /// A journal that records nothing, e.g. for dry runs
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            file: None,
+            error: None,
+        }
+    }
+
+    /// The path of the journal, once there is one to undo
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref().filter(|path| path.exists())
+    }
+
+    /// Why the journal could not be written, if it failed
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    pub fn record(&mut self, entry: Entry) -> Result<(), ReadError> {
+        if let Some(msg) = &self.error {
+            return Err(ReadError { msg: msg.clone() });
+        }
+        let result = self.write(&entry);
+        if let Err(err) = &result {
+            self.error = Some(err.msg.clone());
+        }
+        result
+    }
+
+    fn write(&mut self, entry: &Entry) -> Result<(), ReadError> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let line = entry.to_line().map_err(|err| ReadError {
+            msg: format!("Could not journal {:?}: {}", entry, err),
+        })?;
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .map_err(|err| ReadError {
+                        msg: format!("Could not create journal {}: {}", path.display(), err),
+                    })?;
+                self.file.insert(file)
+            }
+        };
+        // written unbuffered, so the journal is complete up to the last change even if the run is killed
+        writeln!(file, "{}", line).map_err(|err| ReadError {
+            msg: format!("Could not write journal {}: {}", path.display(), err),
+        })
+    }
+}
+
+impl Entry {
+    fn to_line(&self) -> anyhow::Result<String> {
+        let line = match self {
+            Entry::Copy { from, to, replaced } => {
+                let kind = if *replaced { "copy-replace" } else { "copy" };
+                format!("{}\t{}\t{}", kind, escape(from)?, escape(to)?)
+            }
+            Entry::Move { from, to, replaced } => {
+                let kind = if *replaced { "move-replace" } else { "move" };
+                format!("{}\t{}\t{}", kind, escape(from)?, escape(to)?)
+            }
+            Entry::CreateDir(dir) => format!("mkdir\t{}", escape(dir)?),
+            Entry::RemoveDir(dir) => format!("rmdir\t{}", escape(dir)?),
+        };
+        Ok(line)
+    }
+
+    fn from_line(line: &str) -> anyhow::Result<Self> {
+        let parts: Vec<PathBuf> = line.split('\t').skip(1).map(unescape).collect();
+        let kind = line.split('\t').next().unwrap_or_default();
+        let entry = match (kind, parts.as_slice()) {
+            ("copy" | "copy-replace", [from, to]) => Entry::Copy {
+                from: from.clone(),
+                to: to.clone(),
+                replaced: kind == "copy-replace",
+            },
+            ("move" | "move-replace", [from, to]) => Entry::Move {
+                from: from.clone(),
+                to: to.clone(),
+                replaced: kind == "move-replace",
+            },
+            ("mkdir", [dir]) => Entry::CreateDir(dir.clone()),
+            ("rmdir", [dir]) => Entry::RemoveDir(dir.clone()),
+            _ => bail!("Invalid journal line: {}", line),
+        };
+        Ok(entry)
+    }
+}
+
+pub fn escape(path: &Path) -> anyhow::Result<String> {
+    let absolute = std::path::absolute(path)?;
+    let str = absolute
+        .to_str()
+        .ok_or(anyhow!("Path is not valid UTF-8"))?;
+    Ok(str
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n"))
+}
+
+pub fn unescape(value: &str) -> PathBuf {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    PathBuf::from(result)
+}
+
+pub fn read(journal: &Path) -> anyhow::Result<Vec<Entry>> {
+    let content = fs::read_to_string(journal)
+        .with_context(|| format!("Could not read journal {}", journal.display()))?;
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(Entry::from_line)
+        .collect()
+}
+
+/// Reverts the entries of the journal, last one first. Returns the number of entries that could not be reverted.
+pub fn undo(journal: &Path, dry_run: bool, shell: &mut Shell) -> anyhow::Result<usize> {
+    let entries = read(journal)?;
+    let mut failed = 0;
+    for entry in entries.iter().rev() {
+        if dry_run {
+            shell.println(PrintLevel::Normal, || format!("Would revert {:?}", entry));
+            continue;
+        }
+        match revert(entry) {
+            Ok(()) => shell.println(PrintLevel::Verbose, || format!("Reverted {:?}", entry)),
+            Err(err) => {
+                eprintln!("Failed to revert {:?}: {}", entry, err.msg);
+                failed += 1;
+            }
+        }
+    }
+    Ok(failed)
+}
+
+fn revert(entry: &Entry) -> Result<(), ReadError> {
+    let to_read_error = |err: std::io::Error| ReadError {
+        msg: err.to_string(),
+    };
+    match entry {
+        Entry::Copy {
+            replaced: true, to, ..
+        } => Err(ReadError {
+            msg: format!(
+                "{} replaced another file which cannot be restored",
+                to.display()
+            ),
+        }),
+        Entry::Copy { to, .. } => fs::remove_file(to).map_err(to_read_error),
+        Entry::Move { from, to, replaced } => {
+            if from.exists() {
+                return Err(ReadError {
+                    msg: format!("{} exists again", from.display()),
+                });
+            }
+            if let Some(parent) = from.parent() {
+                fs::create_dir_all(parent).map_err(to_read_error)?;
+            }
+            if let Moved::Copied(_, err) = transfer::move_file(to, from)? {
+                return Err(err);
+            }
+            if *replaced {
+                // the source is back, but what it replaced is gone
+                return Err(ReadError {
+                    msg: format!(
+                        "Moved back to {}, but the file it replaced at {} cannot be restored",
+                        from.display(),
+                        to.display()
+                    ),
+                });
+            }
+            Ok(())
+        }
+        Entry::CreateDir(dir) => fs::remove_dir(dir).map_err(to_read_error),
+        Entry::RemoveDir(dir) => fs::create_dir_all(dir).map_err(to_read_error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use crate::dick_sort::journal::{read, revert, Entry, Journal};
+    use crate::dick_sort::test_util::test_dir;
+
+    #[test]
+    fn line_round_trip() {
+        let entries = [
+            Entry::Copy {
+                from: PathBuf::from("/src/a\tb.jpg"),
+                to: PathBuf::from("/dst/2023/a\\b.jpg"),
+                replaced: false,
+            },
+            Entry::Move {
+                from: PathBuf::from("/src/c.jpg"),
+                to: PathBuf::from("/dst/c\n.jpg"),
+                replaced: true,
+            },
+            Entry::CreateDir(PathBuf::from("/dst/2023")),
+            Entry::RemoveDir(PathBuf::from("/src/old")),
+        ];
+        for entry in entries {
+            let line = entry.to_line().expect("should serialize");
+            assert!(!line.contains('\n'));
+            assert_eq!(Entry::from_line(&line).expect("should parse"), entry);
+        }
+    }
+
+    #[test]
+    fn invalid_line() {
+        assert!(Entry::from_line("copy\t/only/one").is_err());
+        assert!(Entry::from_line("delete\t/a").is_err());
+    }
+
+    #[test]
+    fn created_with_the_first_change() {
+        let dir = test_dir("journal");
+
+        let mut journal = Journal::create(&dir).unwrap();
+        assert_eq!(journal.path(), None);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        journal
+            .record(Entry::Move {
+                from: dir.join("a.jpg"),
+                to: dir.join("b.jpg"),
+                replaced: true,
+            })
+            .unwrap();
+        let path = journal.path().expect("should exist now").to_path_buf();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        // the source is moved back, but the file it replaced is reported as lost
+        fs::write(dir.join("b.jpg"), b"b").unwrap();
+        for entry in read(&path).unwrap() {
+            assert!(revert(&entry).is_err());
+        }
+        assert!(dir.join("a.jpg").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fails_for_good() {
+        let dir = test_dir("journal_fails");
+        let mut journal = Journal::open(dir.join("missing").join("dicksort-1.journal"));
+        let entry = || Entry::CreateDir(dir.join("2023"));
+        assert!(journal.record(entry()).is_err());
+        assert!(journal.error().is_some());
+
+        // even once it could be written, as it would miss the first change
+        fs::create_dir(dir.join("missing")).unwrap();
+        assert!(journal.record(entry()).is_err());
+        assert_eq!(journal.path(), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
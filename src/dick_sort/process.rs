@@ -1,115 +1,557 @@
-use std::collections::VecDeque;
-use std::ffi::OsStr;
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
 use std::fs::remove_dir;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::{fs, io};
 
-use crate::dick_sort::{CopyImage, ReadError};
-use crate::Cli;
+use crate::dick_sort::checkpoint::Checkpoint;
+use crate::dick_sort::conflict::{Resolution, SkipReason};
+use crate::dick_sort::journal::{Entry, Journal};
+use crate::dick_sort::transfer::Moved;
+use crate::dick_sort::{
+    conflict, transfer, CopyImage, DateSource, ReadError, ScannedFile, UndatedFile,
+};
+use crate::progress::{lock, SharedProgressReport};
+use crate::{interrupt, Cli};
 
 use pathdiff::diff_paths;
+use rayon::prelude::*;
+use rayon::ThreadPool;
 
-pub fn process(args: &Cli, mut files: VecDeque<CopyImage>) {
-    while !files.is_empty() {
-        if args.r#move {
-            match move_file(args, &mut files) {
-                Ok(true) => {
-                    // todo: count moves
-                }
-                Err(e) => {
-                    eprintln!("Failed {:?}", e);
-                    copy_and_count(args, &mut files);
-                }
-                _def => {
-                    // todo: count same file
-                }
-            }
-        } else {
-            copy_and_count(args, &mut files);
+/// What happened to a single file
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    /// The file was (or in a dry run would be) copied or moved to the path
+    Transferred(PathBuf),
+    /// The file was copied to the path instead of moved, as its source could not be removed
+    CopiedInstead(PathBuf),
+    /// The same content is already at the path
+    AlreadyPresent(PathBuf),
+    /// The file was left alone because of the conflict policy
+    Skipped,
+}
+
+/// Counts of what happened during processing
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub copied: usize,
+    pub moved: usize,
+    pub already_present: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Transferred files that had no date but their modification or birth time
+    pub dated_by_file_time: usize,
+    /// Media files without a plausible date, which are left alone
+    pub undated: Vec<UndatedFile>,
+}
+
+/// What a single file wants printed, which is collected while the file is processed and printed in input order
+/// afterwards, so parallel processing produces the same output as sequential processing. Changes are journaled right
+/// away, so the journal is complete even if the run is killed in the middle of a batch.
+struct Report<'a> {
+    lines: Vec<String>,
+    /// Failures that do not fail the file, like those of its sidecars
+    errors: Vec<String>,
+    journal: &'a Mutex<&'a mut Journal>,
+}
+
+impl<'a> Report<'a> {
+    fn new(journal: &'a Mutex<&'a mut Journal>) -> Self {
+        Self {
+            lines: Vec::new(),
+            errors: Vec::new(),
+            journal,
         }
     }
+
+    fn record(&mut self, entry: Entry) -> Result<(), ReadError> {
+        self.journal
+            .lock()
+            .expect("The journal should not be poisoned")
+            .record(entry)
+    }
 }
 
-fn copy_and_count(args: &Cli, files: &mut VecDeque<CopyImage>) {
-    match copy_file(args, files) {
-        Ok(true) => {
-            // count copies
-        }
-        Err(e) => {
-            eprintln!("Failed copy {:?}", e);
+pub fn process(
+    args: &Cli,
+    files: impl IntoIterator<Item = ScannedFile>,
+    journal: &mut Journal,
+    checkpoint: &mut Checkpoint,
+    progress: &SharedProgressReport,
+    pool: &ThreadPool,
+) -> Summary {
+    let mut summary = Summary::default();
+    let mut files = files.into_iter();
+    // with a single job every file is its own batch, just like sequential processing
+    let batch_size = if args.jobs > 1 { args.jobs * 8 } else { 1 };
+
+    // a change the journal misses could not be undone, so the run stops
+    while !interrupt::requested() && journal.error().is_none() {
+        let batch: Vec<ScannedFile> = files
+            .by_ref()
+            .filter_map(|file| match file {
+                // without an undated dir they are left alone
+                ScannedFile::Undated(undated) if args.undated.is_none() => {
+                    summary.undated.push(undated);
+                    None
+                }
+                file => Some(file),
+            })
+            .filter(|file| !checkpoint.was_processed(file.source()))
+            .take(batch_size)
+            .collect();
+        if batch.is_empty() {
+            break;
         }
-        _def => {
-            // count same file
+
+        // creating the dirs is cheap and journaled, so it happens in order before the parallel part
+        let planned: Vec<Planned> = batch
+            .into_iter()
+            .enumerate()
+            .map(|(index, file)| Planned {
+                index,
+                path: build_and_create_path(args, &file, journal),
+                file,
+            })
+            .collect();
+
+        let shared_journal = Mutex::new(&mut *journal);
+        let mut results = pool.install(|| {
+            group_by_name(planned)
+                .into_par_iter()
+                .flat_map_iter(|group| {
+                    group.into_iter().filter_map(|planned| {
+                        // files not started yet are left for the resumed run
+                        if interrupt::requested() || journal_failed(&shared_journal) {
+                            return None;
+                        }
+                        let Planned { index, file, path } = planned;
+                        let mut report = Report::new(&shared_journal);
+                        let outcome = path.and_then(|path| {
+                            transfer_with_sidecars(args, &file, path, &mut report)
+                        });
+                        Some(Processed {
+                            index,
+                            file,
+                            outcome,
+                            lines: report.lines,
+                            errors: report.errors,
+                        })
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+
+        results.sort_by_key(|processed| processed.index);
+        for processed in results {
+            apply(args, processed, journal, checkpoint, progress, &mut summary);
         }
     }
+    summary
 }
 
-fn copy_file(args: &Cli, files: &mut VecDeque<CopyImage>) -> Result<bool, ReadError> {
-    let (path, image) = build_and_create_path(args, files)?;
+fn journal_failed(journal: &Mutex<&mut Journal>) -> bool {
+    journal
+        .lock()
+        .expect("The journal should not be poisoned")
+        .error()
+        .is_some()
+}
 
-    if !image.source.eq(&path) {
-        return if args.dry_run {
-            let relative_source = diff_paths(&image.source, &args.source_dir).unwrap();
-            let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
-            println!(
-                "Would copy from {:?} to {:?}",
-                relative_source, relative_destination
-            );
-            Ok(false)
-        } else {
-            let size = fs::copy(image.source, &path).map_err(|err| ReadError {
-                msg: err.to_string(),
-            })?;
-            if args.verbose {
-                let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
-                println!("Copied {:?} bytes to {:?}", size, relative_destination);
+/// A file of a batch with its destination. The index keeps track of the input order.
+struct Planned {
+    index: usize,
+    file: ScannedFile,
+    path: Result<PathBuf, ReadError>,
+}
+
+/// A file of a batch after processing, with what it wants printed
+struct Processed {
+    index: usize,
+    file: ScannedFile,
+    outcome: Result<Outcome, ReadError>,
+    lines: Vec<String>,
+    errors: Vec<String>,
+}
+
+/// Groups files whose destinations might collide, e.g. `a.jpg` and `a_1.jpg` in the same dir, so they are processed
+/// one after another while the groups run in parallel.
+fn group_by_name(planned: Vec<Planned>) -> Vec<Vec<Planned>> {
+    let mut groups: Vec<Vec<Planned>> = Vec::new();
+    let mut group_of_key: HashMap<Option<(PathBuf, String)>, usize> = HashMap::new();
+    for file in planned {
+        let key = file.path.as_ref().ok().map(|path| {
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_lowercase())
+                .unwrap_or_default();
+            // numbered suffixes may be the result of renaming on conflict, even repeatedly, e.g. `a_1_1.jpg`
+            let mut base = stem.as_str();
+            while let Some((rest, n)) = base.rsplit_once('_') {
+                if n.is_empty() || !n.chars().all(|c| c.is_ascii_digit()) {
+                    break;
+                }
+                base = rest;
             }
-            Ok(true)
-        };
+            (
+                path.parent().map(Path::to_path_buf).unwrap_or_default(),
+                base.to_string(),
+            )
+        });
+        let group = *group_of_key.entry(key).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(file);
     }
-    Ok(false)
+    groups
 }
 
-fn move_file(args: &Cli, files: &mut VecDeque<CopyImage>) -> Result<bool, ReadError> {
-    let (path, image) = build_and_create_path(args, files)?;
-
-    if !image.source.eq(&path) {
-        return if args.dry_run {
-            let relative_source = diff_paths(&image.source, &args.source_dir).unwrap();
-            let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
-            println!(
-                "Would move from {:?} to {:?}",
-                relative_source, relative_destination
-            );
-            Ok(false)
-        } else {
-            fs::rename(&image.source, &path).map_err(|err| ReadError {
-                msg: err.to_string(),
-            })?;
-            let size = fs::metadata(&path).unwrap().len();
-            if args.verbose {
-                let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
-                println!("Moved {:?} bytes to {:?}", size, relative_destination);
+/// Prints and counts the result of a file
+fn apply(
+    args: &Cli,
+    processed: Processed,
+    journal: &mut Journal,
+    checkpoint: &mut Checkpoint,
+    progress: &SharedProgressReport,
+    summary: &mut Summary,
+) {
+    let Processed {
+        file,
+        outcome,
+        mut lines,
+        mut errors,
+        ..
+    } = processed;
+    let source = file.source().to_path_buf();
+    if let Ok(Outcome::Transferred(_) | Outcome::CopiedInstead(_)) = outcome {
+        match file {
+            ScannedFile::Dated(image) if image.date_source == DateSource::FileTime => {
+                summary.dated_by_file_time += 1
             }
-            if args.clean {
+            ScannedFile::Dated(_) => {}
+            ScannedFile::Undated(undated) => summary.undated.push(undated),
+        }
+    }
+    match outcome {
+        Ok(Outcome::Transferred(_)) if args.r#move => {
+            summary.moved += 1;
+            if args.clean && !args.dry_run {
                 // If we can't delete it's no reason to stop moving
                 let _ = clean_empty_to_root(
                     args,
-                    &image
-                        .source
+                    journal,
+                    &mut lines,
+                    &source
                         .parent()
                         .expect("A file should have a parent")
                         .to_path_buf(),
                     &args.source_dir,
                 );
             }
-            Ok(true)
+        }
+        Ok(Outcome::Transferred(_) | Outcome::CopiedInstead(_)) => summary.copied += 1,
+        Ok(Outcome::AlreadyPresent(_)) => summary.already_present += 1,
+        Ok(Outcome::Skipped) => summary.skipped += 1,
+        Err(e) => {
+            let action = if args.r#move { "move" } else { "copy" };
+            errors.push(format!("Failed {} {}", action, e.msg));
+            print(progress, lines, errors);
+            summary.failed += 1;
+            // failed files are retried when resuming
+            return;
+        }
+    }
+    // the file is just done again when resuming, so this is no reason to stop
+    if let Err(err) = checkpoint.mark_processed(&source) {
+        errors.push(format!("{:#}", err));
+    }
+    print(progress, lines, errors);
+}
+
+/// Prints the output of a file above the progress report
+fn print(progress: &SharedProgressReport, lines: Vec<String>, errors: Vec<String>) {
+    let mut progress = lock(progress);
+    for line in lines {
+        progress.println(&line);
+    }
+    for error in errors {
+        progress.eprintln(&error);
+    }
+}
+
+/// Copies or moves the file, then its sidecars next to it under the name it ended up with. If the file is already
+/// present, its sidecars are only copied next to the present one, through the conflict policy, as the file stays in
+/// the source. Sidecars of a skipped file stay with it. A failed sidecar is reported without failing its file, which
+/// may already be moved.
+fn transfer_with_sidecars(
+    args: &Cli,
+    file: &ScannedFile,
+    path: PathBuf,
+    report: &mut Report,
+) -> Result<Outcome, ReadError> {
+    let relative_source = diff_paths(file.source(), &args.source_dir).unwrap();
+    match file {
+        // guesses are worth a look before the run, every date is shown when verbose
+        ScannedFile::Dated(image)
+            if args.verbose || (image.date_source.is_guess() && args.dry_run) =>
+        {
+            let tag = image
+                .date_tag
+                .map(|tag| format!(" ({})", tag))
+                .unwrap_or_default();
+            report.lines.push(format!(
+                "{:?} is dated by its {}{}",
+                relative_source, image.date_source, tag
+            ));
+        }
+        ScannedFile::Undated(undated) if args.verbose => {
+            report.lines.push(format!(
+                "{:?} is undated: {}",
+                relative_source, undated.reason
+            ));
+        }
+        _ => {}
+    }
+    let outcome = transfer(args, file.source(), path, args.r#move, report)?;
+    for sidecar in file.sidecars() {
+        let result = match &outcome {
+            Outcome::Transferred(destination) => {
+                let path = sidecar_destination(file.source(), sidecar, destination);
+                transfer_sidecar(args, sidecar, path, args.r#move, report)
+            }
+            // the file stays in the source, so its sidecars do too
+            Outcome::CopiedInstead(destination) => {
+                let path = sidecar_destination(file.source(), sidecar, destination);
+                transfer_sidecar(args, sidecar, path, false, report)
+            }
+            Outcome::AlreadyPresent(existing) => {
+                let path = sidecar_destination(file.source(), sidecar, existing);
+                transfer(args, sidecar, path, false, report).map(|_| ())
+            }
+            Outcome::Skipped => Ok(()),
         };
+        if let Err(err) = result {
+            let relative_sidecar = diff_paths(sidecar, &args.source_dir).unwrap();
+            report.errors.push(format!(
+                "Failed sidecar {:?}: {}",
+                relative_sidecar, err.msg
+            ));
+        }
     }
-    Ok(false)
+    Ok(outcome)
 }
 
-fn clean_empty_to_root(args: &Cli, current: &PathBuf, root: &PathBuf) -> Result<(), ReadError> {
+/// Copies or moves the file to the path, or wherever the conflict policy decides
+fn transfer(
+    args: &Cli,
+    source: &Path,
+    path: PathBuf,
+    moving: bool,
+    report: &mut Report,
+) -> Result<Outcome, ReadError> {
+    if source.eq(&path) {
+        // the file already is where it belongs
+        return Ok(Outcome::AlreadyPresent(path));
+    }
+    let (path, replaced) = match resolve_conflict(args, source, path, report)? {
+        Resolution::Write(path) | Resolution::Rename(path) => (path, false),
+        Resolution::Overwrite(path) => (path, true),
+        Resolution::Skip(SkipReason::AlreadyPresent(existing)) => {
+            return Ok(Outcome::AlreadyPresent(existing))
+        }
+        Resolution::Skip(_) => return Ok(Outcome::Skipped),
+    };
+    if moving {
+        move_file(args, source, path, replaced, report)
+    } else {
+        copy_file(args, source, path, replaced, report)
+    }
+}
+
+/// Copies or moves a sidecar next to its file, which was just written. The conflict policy was applied to the file
+/// already, so a different sidecar at the path belonged to a file it replaced and is replaced too. An identical one is
+/// left alone.
+fn transfer_sidecar(
+    args: &Cli,
+    source: &Path,
+    path: PathBuf,
+    moving: bool,
+    report: &mut Report,
+) -> Result<(), ReadError> {
+    if source.eq(&path) {
+        return Ok(());
+    }
+    let replaced = path.exists();
+    if replaced && conflict::same_content(source, &path)? {
+        return Ok(());
+    }
+    if moving {
+        move_file(args, source, path, replaced, report)?;
+    } else {
+        copy_file(args, source, path, replaced, report)?;
+    }
+    Ok(())
+}
+
+/// The sidecar keeps its name relative to the file, e.g. `IMG_0001.xmp` or `IMG_0001.CR2.xmp` of `IMG_0001.CR2`
+/// become `IMG_0001_1.xmp` or `IMG_0001_1.CR2.xmp` if the file was renamed to `IMG_0001_1.CR2`.
+fn sidecar_destination(source: &Path, sidecar: &Path, destination: &Path) -> PathBuf {
+    let lossy = |name: Option<&OsStr>| {
+        name.map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    // the sidecar was assigned by one of both, see file_scanner::sidecar::assign
+    let named_after_file_name =
+        lossy(sidecar.file_stem()).to_lowercase() == lossy(source.file_name()).to_lowercase();
+    let base = if named_after_file_name {
+        lossy(destination.file_name())
+    } else {
+        lossy(destination.file_stem())
+    };
+    destination.with_file_name(format!("{}.{}", base, lossy(sidecar.extension())))
+}
+
+fn copy_file(
+    args: &Cli,
+    source: &Path,
+    path: PathBuf,
+    replaced: bool,
+    report: &mut Report,
+) -> Result<Outcome, ReadError> {
+    if args.dry_run {
+        let relative_source = diff_paths(source, &args.source_dir).unwrap();
+        let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
+        report.lines.push(format!(
+            "Would copy from {:?} to {:?}",
+            relative_source, relative_destination
+        ));
+        Ok(Outcome::Transferred(path))
+    } else {
+        let size = fs::copy(source, &path).map_err(|err| ReadError {
+            msg: err.to_string(),
+        })?;
+        if args.verbose {
+            let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
+            report.lines.push(format!(
+                "Copied {:?} bytes to {:?}",
+                size, relative_destination
+            ));
+        }
+        report.record(Entry::Copy {
+            from: source.to_path_buf(),
+            to: path.clone(),
+            replaced,
+        })?;
+        Ok(Outcome::Transferred(path))
+    }
+}
+
+fn move_file(
+    args: &Cli,
+    source: &Path,
+    path: PathBuf,
+    replaced: bool,
+    report: &mut Report,
+) -> Result<Outcome, ReadError> {
+    if args.dry_run {
+        let relative_source = diff_paths(source, &args.source_dir).unwrap();
+        let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
+        report.lines.push(format!(
+            "Would move from {:?} to {:?}",
+            relative_source, relative_destination
+        ));
+        Ok(Outcome::Transferred(path))
+    } else {
+        let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
+        match transfer::move_file(source, &path)? {
+            Moved::Moved(size) => {
+                if args.verbose {
+                    report.lines.push(format!(
+                        "Moved {:?} bytes to {:?}",
+                        size, relative_destination
+                    ));
+                }
+                report.record(Entry::Move {
+                    from: source.to_path_buf(),
+                    to: path.clone(),
+                    replaced,
+                })?;
+                Ok(Outcome::Transferred(path))
+            }
+            // the copy is in place, so it is journaled as one rather than failing the file
+            Moved::Copied(size, err) => {
+                report.lines.push(format!(
+                    "Copied {:?} bytes to {:?} instead of moving: {}",
+                    size, relative_destination, err.msg
+                ));
+                report.record(Entry::Copy {
+                    from: source.to_path_buf(),
+                    to: path.clone(),
+                    replaced,
+                })?;
+                Ok(Outcome::CopiedInstead(path))
+            }
+        }
+    }
+}
+
+/// Checks the destination against the conflict policy and reports the decision.
+fn resolve_conflict(
+    args: &Cli,
+    source: &Path,
+    path: PathBuf,
+    report: &mut Report,
+) -> Result<Resolution, ReadError> {
+    let resolution = conflict::resolve(args.on_conflict, source, path.clone())?;
+    let relative_source = diff_paths(source, &args.source_dir).unwrap();
+    let relative_destination = diff_paths(&path, &args.destination_dir).unwrap();
+    match &resolution {
+        Resolution::Write(_) => {}
+        Resolution::Overwrite(_) => {
+            report.lines.push(format!(
+                "Overwriting {:?} with {:?}",
+                relative_destination, relative_source
+            ));
+        }
+        Resolution::Rename(renamed) => {
+            let renamed = diff_paths(renamed, &args.destination_dir).unwrap();
+            report.lines.push(format!(
+                "{:?} already exists, using {:?} for {:?}",
+                relative_destination, renamed, relative_source
+            ));
+        }
+        Resolution::Skip(SkipReason::Exists) => {
+            report.lines.push(format!(
+                "Skipping {:?}, {:?} already exists",
+                relative_source, relative_destination
+            ));
+        }
+        Resolution::Skip(SkipReason::NotNewer) => {
+            report.lines.push(format!(
+                "Skipping {:?}, {:?} is not older",
+                relative_source, relative_destination
+            ));
+        }
+        Resolution::Skip(SkipReason::AlreadyPresent(existing)) => {
+            let existing = diff_paths(existing, &args.destination_dir).unwrap();
+            report.lines.push(format!(
+                "Skipping {:?}, it is already present as {:?}",
+                relative_source, existing
+            ));
+        }
+    }
+    Ok(resolution)
+}
+
+fn clean_empty_to_root(
+    args: &Cli,
+    journal: &mut Journal,
+    lines: &mut Vec<String>,
+    current: &PathBuf,
+    root: &PathBuf,
+) -> Result<(), ReadError> {
     // while we haven't reached the root dir, we process parents
     let recurse = current != root;
 
@@ -121,14 +563,17 @@ fn clean_empty_to_root(args: &Cli, current: &PathBuf, root: &PathBuf) -> Result<
         });
     }
 
-    return match remove_dir(current) {
+    match remove_dir(current) {
         Ok(_) => {
+            journal.record(Entry::RemoveDir(current.clone()))?;
             if args.verbose {
-                println!("Deleted empty dir {}", current.display())
+                lines.push(format!("Deleted empty dir {}", current.display()));
             }
             if recurse {
                 return match current.parent() {
-                    Some(path) => clean_empty_to_root(args, &path.to_path_buf(), root),
+                    Some(path) => {
+                        clean_empty_to_root(args, journal, lines, &path.to_path_buf(), root)
+                    }
                     _ => Ok(()),
                 };
             }
@@ -138,68 +583,250 @@ fn clean_empty_to_root(args: &Cli, current: &PathBuf, root: &PathBuf) -> Result<
         Err(err) => Err(ReadError {
             msg: err.to_string(),
         }),
-    };
+    }
 }
 
 fn build_and_create_path(
     args: &Cli,
-    files: &mut VecDeque<CopyImage>,
-) -> Result<(PathBuf, CopyImage), ReadError> {
-    let destination = args.destination_dir.to_str().ok_or(ReadError {
-        msg: "destination dir has no string".to_string(),
-    })?;
-    let image = files.pop_front().ok_or(ReadError {
-        msg: "No more elements in queue".to_string(),
-    })?;
-    let name = image.source.file_name().ok_or(ReadError {
+    file: &ScannedFile,
+    journal: &mut Journal,
+) -> Result<PathBuf, ReadError> {
+    let name = file.source().file_name().ok_or(ReadError {
         msg: "File has no filename".to_string(),
     })?;
 
-    let path = create_sub_path(args, destination, &image, name)?;
-    Ok((path, image))
+    match file {
+        ScannedFile::Dated(image) => create_sub_path(args, journal, image, name),
+        ScannedFile::Undated(undated) => create_undated_path(args, journal, undated, name),
+    }
+}
+
+/// The path in the undated dir, which keeps the path relative to the source dir if wanted
+fn create_undated_path(
+    args: &Cli,
+    journal: &mut Journal,
+    undated: &UndatedFile,
+    file_name: &OsStr,
+) -> Result<PathBuf, ReadError> {
+    let undated_dir = args
+        .undated
+        .as_ref()
+        .expect("Undated files are only processed with an undated dir");
+    let mut dir = args.destination_dir.join(undated_dir);
+    if args.undated_keep_paths {
+        let relative = undated
+            .source
+            .parent()
+            .and_then(|parent| parent.strip_prefix(&args.source_dir).ok());
+        dir.extend(relative);
+    }
+    if !args.dry_run {
+        create_dirs(journal, &dir)?;
+    }
+    Ok(dir.join(file_name))
 }
 
 fn create_sub_path(
     args: &Cli,
-    dest: &str,
+    journal: &mut Journal,
     image: &CopyImage,
     file_name: &OsStr,
 ) -> Result<PathBuf, ReadError> {
-    // replace placeholders with exif value
-    let mut relative_path = args
-        .format
-        .replace(
-            "[YEAR]",
-            format!("{:04}", image.date_time.year).as_str(),
-        )
-        .replace(
-            "[MONTH]",
-            format!("{:02}", image.date_time.month).as_str(),
-        )
-        .replace(
-            "[DAY]",
-            format!("{:02}", image.date_time.day).as_str(),
-        );
-
-    // add file name
-    relative_path.push_str(file_name.to_str().unwrap());
-    // make absolute
-    let mut absolut = dest.to_string();
-    absolut.push_str(relative_path.as_str());
+    // replace placeholders with the values of the image
+    let relative_path = args.format.render(image, &args.source_dir, args.language);
 
-    // create PathBuf
-    let absolute_path = PathBuf::from(absolut);
+    // add file name and make absolute, the destination dir may or may not end with a separator
+    let mut relative_path = OsString::from(relative_path.trim_start_matches('/'));
+    relative_path.push(file_name);
+    let absolute_path = args.destination_dir.join(relative_path);
     if !args.dry_run {
         // create parent dirs
-        fs::create_dir_all(
+        create_dirs(
+            journal,
             absolute_path
                 .parent()
                 .expect("The file should have a parent dir"),
-        )
-        .map_err(|err| ReadError {
-            msg: err.to_string(),
-        })?;
+        )?;
     }
 
     Ok(absolute_path)
 }
+
+/// Creates the dir and all missing parents, journaling each one that did not exist before
+fn create_dirs(journal: &mut Journal, dir: &Path) -> Result<(), ReadError> {
+    let missing: Vec<PathBuf> = dir
+        .ancestors()
+        .take_while(|ancestor| !ancestor.exists())
+        .map(Path::to_path_buf)
+        .collect();
+    fs::create_dir_all(dir).map_err(|err| ReadError {
+        msg: err.to_string(),
+    })?;
+    for created in missing.into_iter().rev() {
+        journal.record(Entry::CreateDir(created))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use clap::Parser;
+
+    use crate::dick_sort::journal::Journal;
+    use crate::dick_sort::process::{
+        build_and_create_path, group_by_name, sidecar_destination, transfer_with_sidecars, Outcome,
+        Planned, Report,
+    };
+    use crate::dick_sort::test_util::test_dir;
+    use crate::dick_sort::{CopyImage, DateSource, ScannedFile, SortedDayTime, UndatedFile};
+    use crate::Cli;
+
+    fn planned(index: usize, path: &str) -> Planned {
+        Planned {
+            index,
+            file: ScannedFile::Dated(CopyImage {
+                source: PathBuf::from("/src").join(index.to_string()),
+                date_time: SortedDayTime {
+                    year: 2023,
+                    month: 1,
+                    day: 5,
+                    hour: 0,
+                    minute: 0,
+                    second: 0,
+                    nanosecond: None,
+                    offset: None,
+                },
+                date_source: DateSource::Exif,
+                date_tag: None,
+                exif: HashMap::new(),
+                sidecars: Vec::new(),
+            }),
+            path: Ok(PathBuf::from(path)),
+        }
+    }
+
+    #[test]
+    fn colliding_names_share_a_group() {
+        let groups = group_by_name(vec![
+            planned(0, "/dst/2023/a.jpg"),
+            planned(1, "/dst/2023/b.jpg"),
+            planned(2, "/dst/2023/A_1.jpg"),
+            planned(3, "/dst/2022/a.jpg"),
+            planned(4, "/dst/2023/a_1_1.jpg"),
+            planned(5, "/dst/2023/a_b_1.jpg"),
+        ]);
+        let indices: Vec<Vec<usize>> = groups
+            .iter()
+            .map(|group| group.iter().map(|file| file.index).collect())
+            .collect();
+        assert_eq!(indices, vec![vec![0, 2, 4], vec![1], vec![3], vec![5]]);
+    }
+
+    #[test]
+    fn undated_keep_their_relative_path() {
+        let undated = ScannedFile::Undated(UndatedFile {
+            source: PathBuf::from("/src/2011/scans/a.jpg"),
+            reason: "No Date Time in file".to_string(),
+            sidecars: Vec::new(),
+        });
+        let path = |extra: &[&str]| {
+            let args = Cli::parse_from(
+                ["dicksort", "-d", "/src", "/dst", "--undated", "undated"]
+                    .iter()
+                    .chain(extra),
+            );
+            build_and_create_path(&args, &undated, &mut Journal::disabled()).unwrap()
+        };
+        assert_eq!(path(&[]), PathBuf::from("/dst/undated/a.jpg"));
+        assert_eq!(
+            path(&["--undated-keep-paths"]),
+            PathBuf::from("/dst/undated/2011/scans/a.jpg")
+        );
+    }
+
+    #[test]
+    fn sidecars_follow_the_new_name() {
+        let source = PathBuf::from("/src/IMG_1.CR2");
+        let destination = PathBuf::from("/dst/2023/IMG_1_1.CR2");
+        assert_eq!(
+            sidecar_destination(&source, &PathBuf::from("/src/IMG_1.xmp"), &destination),
+            PathBuf::from("/dst/2023/IMG_1_1.xmp")
+        );
+        assert_eq!(
+            sidecar_destination(&source, &PathBuf::from("/src/img_1.cr2.pp3"), &destination),
+            PathBuf::from("/dst/2023/IMG_1_1.CR2.pp3")
+        );
+    }
+
+    #[test]
+    fn sidecars_follow_the_decision_of_their_file() {
+        let dir = test_dir("process");
+        let (source, destination) = (dir.join("src"), dir.join("dst"));
+        fs::create_dir_all(&source).unwrap();
+        fs::create_dir_all(&destination).unwrap();
+        fs::write(source.join("IMG_1.CR2"), b"raw").unwrap();
+        fs::write(source.join("IMG_1.xmp"), b"edited").unwrap();
+        fs::write(destination.join("IMG_1.CR2"), b"raw").unwrap();
+        fs::write(destination.join("IMG_1.xmp"), b"original").unwrap();
+        let file = ScannedFile::Undated(UndatedFile {
+            source: source.join("IMG_1.CR2"),
+            reason: "No Date Time in file".to_string(),
+            sidecars: vec![source.join("IMG_1.xmp")],
+        });
+
+        let mut journal = Journal::disabled();
+        let journal = Mutex::new(&mut journal);
+        let transfer = |on_conflict: &str| {
+            let args = Cli::parse_from([
+                "dicksort",
+                "-m",
+                "--on-conflict",
+                on_conflict,
+                source.to_str().unwrap(),
+                destination.to_str().unwrap(),
+            ]);
+            let mut report = Report::new(&journal);
+            transfer_with_sidecars(&args, &file, destination.join("IMG_1.CR2"), &mut report)
+                .unwrap()
+        };
+
+        fs::write(destination.join("IMG_1.CR2"), b"other").unwrap();
+        assert_eq!(transfer("skip"), Outcome::Skipped);
+        assert!(source.join("IMG_1.xmp").exists());
+        assert_eq!(
+            fs::read(destination.join("IMG_1.xmp")).unwrap(),
+            b"original"
+        );
+
+        // the file stays in the source, so its sidecar is only copied, through the policy
+        fs::write(destination.join("IMG_1.CR2"), b"raw").unwrap();
+        let present = Outcome::AlreadyPresent(destination.join("IMG_1.CR2"));
+        assert_eq!(transfer("skip"), present);
+        assert!(!destination.join("IMG_1_1.xmp").exists());
+        assert_eq!(transfer("rename"), present);
+        assert!(source.join("IMG_1.xmp").exists());
+        assert_eq!(
+            fs::read(destination.join("IMG_1.xmp")).unwrap(),
+            b"original"
+        );
+        assert_eq!(
+            fs::read(destination.join("IMG_1_1.xmp")).unwrap(),
+            b"edited"
+        );
+
+        // the moved file takes its sidecar along
+        fs::remove_file(destination.join("IMG_1.CR2")).unwrap();
+        assert_eq!(
+            transfer("rename"),
+            Outcome::Transferred(destination.join("IMG_1.CR2"))
+        );
+        assert!(!source.join("IMG_1.xmp").exists());
+        assert_eq!(fs::read(destination.join("IMG_1.xmp")).unwrap(), b"edited");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
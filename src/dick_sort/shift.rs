@@ -0,0 +1,226 @@
+use std::fs;
+
+use chrono::{Datelike, Months, NaiveDate, TimeDelta};
+
+use crate::dick_sort::SortedDayTime;
+
+/// A correction of a camera clock, e.g. `+1y-1h`. Months are kept apart from the rest, as they differ in length.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Shift {
+    months: i32,
+    seconds: i64,
+}
+
+/// Parses a shift like `+1y-1h` or `-1h30m` of years (`y`), months (`mo`), weeks (`w`), days (`d`), hours (`h`),
+/// minutes (`m`) and seconds (`s`). A part without a sign takes the sign of the part before it.
+pub fn parse_shift(value: &str) -> Result<Shift, String> {
+    let value = value.trim();
+    let invalid = || {
+        format!(
+            "{} is no shift like +1y-1h of y, mo, w, d, h, m and s",
+            value
+        )
+    };
+    let mut shift = Shift::default();
+    let mut sign = 1;
+    let mut rest = value;
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        if let Some(unsigned) = rest.strip_prefix('+') {
+            sign = 1;
+            rest = unsigned;
+        } else if let Some(unsigned) = rest.strip_prefix('-') {
+            sign = -1;
+            rest = unsigned;
+        }
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let amount = i64::from(rest[..digits].parse::<u32>().map_err(|_| invalid())?);
+        let unit_len = rest[digits..]
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len() - digits);
+        let unit = &rest[digits..digits + unit_len];
+        let amount = sign * amount;
+        match unit {
+            "y" => shift.months += i32::try_from(amount * 12).map_err(|_| invalid())?,
+            "mo" => shift.months += i32::try_from(amount).map_err(|_| invalid())?,
+            "w" => shift.seconds += amount * 7 * 86400,
+            "d" => shift.seconds += amount * 86400,
+            "h" => shift.seconds += amount * 3600,
+            "m" => shift.seconds += amount * 60,
+            "s" => shift.seconds += amount,
+            _ => return Err(invalid()),
+        }
+        rest = &rest[digits + unit_len..];
+    }
+    Ok(shift)
+}
+
+impl Shift {
+    /// The date moved by the shift. Partial dates, e.g. of folders, are not written by a camera and kept as they are,
+    /// just like dates the shift would move out of the calendar.
+    pub fn apply(&self, date: &SortedDayTime) -> SortedDayTime {
+        self.try_apply(date).unwrap_or_else(|| date.clone())
+    }
+
+    fn try_apply(&self, date: &SortedDayTime) -> Option<SortedDayTime> {
+        let date_time = NaiveDate::from_ymd_opt(
+            i32::from(date.year),
+            u32::from(date.month),
+            u32::from(date.day),
+        )?
+        .and_hms_opt(
+            u32::from(date.hour),
+            u32::from(date.minute),
+            u32::from(date.second),
+        )?;
+        let months = Months::new(self.months.unsigned_abs());
+        let date_time = if self.months < 0 {
+            date_time.checked_sub_months(months)?
+        } else {
+            date_time.checked_add_months(months)?
+        };
+        let date_time = date_time.checked_add_signed(TimeDelta::try_seconds(self.seconds)?)?;
+        u16::try_from(date_time.year()).ok()?;
+        Some(SortedDayTime {
+            nanosecond: date.nanosecond,
+            offset: date.offset,
+            ..SortedDayTime::from(date_time)
+        })
+    }
+}
+
+/// Shifts of single cameras, read from a file with one `<camera> = <shift>` per line, e.g.
+/// `Canon EOS 5D Mark IV = +1y-1h`. The camera is the `BodySerialNumber`, the `Model` or `Make` and `Model` of the
+/// exif, compared ignoring case. Empty lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CameraShifts(Vec<(String, Shift)>);
+
+/// Reads the camera shifts from the file at the path
+pub fn parse_camera_shifts(path: &str) -> Result<CameraShifts, String> {
+    let content =
+        fs::read_to_string(path).map_err(|err| format!("Could not read {}: {}", path, err))?;
+    CameraShifts::parse(&content)
+}
+
+/// The exif fields a camera is told apart by
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Camera {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+}
+
+impl CameraShifts {
+    fn parse(content: &str) -> Result<Self, String> {
+        let mut shifts = Vec::new();
+        for (number, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (camera, shift) = line
+                .rsplit_once('=')
+                .filter(|(camera, _)| !camera.trim().is_empty())
+                .ok_or_else(|| format!("Line {} is no <camera> = <shift>: {}", number + 1, line))?;
+            let shift =
+                parse_shift(shift).map_err(|err| format!("Line {}: {}", number + 1, err))?;
+            shifts.push((camera.trim().to_lowercase(), shift));
+        }
+        Ok(Self(shifts))
+    }
+
+    /// The shift of the camera, looked up by its serial number first, as that tells apart two bodies of one model
+    pub fn find(&self, camera: &Camera) -> Option<Shift> {
+        let make_model = match (&camera.make, &camera.model) {
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            _ => None,
+        };
+        let names: Vec<String> = [&camera.serial, &make_model, &camera.model]
+            .into_iter()
+            .flatten()
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        names.iter().find_map(|name| {
+            self.0
+                .iter()
+                .find(|(camera, _)| camera == name)
+                .map(|(_, shift)| *shift)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDateTime;
+
+    use crate::dick_sort::shift::{parse_shift, Camera, CameraShifts};
+    use crate::dick_sort::SortedDayTime;
+
+    fn shifted(shift: &str, date: &str) -> String {
+        let date: NaiveDateTime = date.parse().unwrap();
+        let shift = parse_shift(shift).expect("should be a shift");
+        shift.apply(&SortedDayTime::from(date)).to_string()
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(
+            shifted("+1y-1h", "2023-01-05T00:30:00"),
+            "2024-01-04 23:30:00"
+        );
+        assert_eq!(
+            shifted("-1h30m", "2023-01-05T12:00:00"),
+            "2023-01-05 10:30:00"
+        );
+        assert_eq!(
+            shifted("+1mo", "2023-01-31T12:00:00"),
+            "2023-02-28 12:00:00"
+        );
+        assert_eq!(shifted("2w", "2023-01-05T12:00:00"), "2023-01-19 12:00:00");
+        for invalid in ["", "+", "1", "1x", "+1y-", "1.5h"] {
+            assert!(
+                parse_shift(invalid).is_err(),
+                "{} should be invalid",
+                invalid
+            );
+        }
+
+        let partial = SortedDayTime {
+            day: 0,
+            ..SortedDayTime::from("2023-01-05T12:00:00".parse::<NaiveDateTime>().unwrap())
+        };
+        assert_eq!(parse_shift("+1d").unwrap().apply(&partial), partial);
+    }
+
+    #[test]
+    fn camera_shifts() {
+        let shifts = CameraShifts::parse(
+            "# second body\n\nCanon EOS 5D Mark IV = +1y-1h\n012345 = -30m\nNIKON CORPORATION NIKON D750 = +1d\n",
+        )
+        .expect("should parse");
+        let camera = |make: &str, model: &str, serial: Option<&str>| Camera {
+            make: Some(make.to_string()),
+            model: Some(model.to_string()),
+            serial: serial.map(str::to_string),
+        };
+        assert_eq!(
+            shifts.find(&camera("Canon", "canon eos 5d mark iv", None)),
+            parse_shift("+1y-1h").ok()
+        );
+        assert_eq!(
+            shifts.find(&camera("Canon", "Canon EOS 5D Mark IV", Some("012345"))),
+            parse_shift("-30m").ok()
+        );
+        assert_eq!(
+            shifts.find(&camera("NIKON CORPORATION", "NIKON D750", None)),
+            parse_shift("+1d").ok()
+        );
+        assert_eq!(shifts.find(&camera("Canon", "Canon EOS R5", None)), None);
+        assert!(CameraShifts::parse("Canon EOS R5 +1h").is_err());
+        assert!(CameraShifts::parse("Canon EOS R5 = soon").is_err());
+    }
+}
@@ -0,0 +1,562 @@
+use std::env;
+use std::ffi::OsStr;
+use std::path::Path;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+
+use crate::dick_sort::CopyImage;
+
+/// The language of the names of months and weekdays
+#[derive(Debug, Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum Language {
+    /// English
+    En,
+    /// German
+    De,
+    /// French
+    Fr,
+    /// Spanish
+    Es,
+    /// Italian
+    It,
+    /// Dutch
+    Nl,
+}
+
+impl Language {
+    /// The language of `LC_ALL`, `LC_TIME` or `LANG`, e.g. `de_DE.UTF-8`, falling back to English
+    pub fn from_env() -> Self {
+        let locale = ["LC_ALL", "LC_TIME", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        match locale.get(..2).map(str::to_lowercase).as_deref() {
+            Some("de") => Language::De,
+            Some("fr") => Language::Fr,
+            Some("es") => Language::Es,
+            Some("it") => Language::It,
+            Some("nl") => Language::Nl,
+            _ => Language::En,
+        }
+    }
+
+    /// The names from January to December, separated by spaces
+    fn month_names(&self) -> &'static str {
+        match self {
+            Language::En => "January February March April May June July August September October November December",
+            Language::De => "Januar Februar März April Mai Juni Juli August September Oktober November Dezember",
+            Language::Fr => "janvier février mars avril mai juin juillet août septembre octobre novembre décembre",
+            Language::Es => "enero febrero marzo abril mayo junio julio agosto septiembre octubre noviembre diciembre",
+            Language::It => "gennaio febbraio marzo aprile maggio giugno luglio agosto settembre ottobre novembre dicembre",
+            Language::Nl => "januari februari maart april mei juni juli augustus september oktober november december",
+        }
+    }
+
+    /// The abbreviated names from Monday to Sunday, separated by spaces
+    fn weekday_names(&self) -> &'static str {
+        match self {
+            Language::En => "Mon Tue Wed Thu Fri Sat Sun",
+            Language::De => "Mo Di Mi Do Fr Sa So",
+            Language::Fr => "lun mar mer jeu ven sam dim",
+            Language::Es => "lun mar mié jue vie sáb dom",
+            Language::It => "lun mar mer gio ven sab dom",
+            Language::Nl => "ma di wo do vr za zo",
+        }
+    }
+
+    fn month_name(&self, month: u8) -> Option<&'static str> {
+        let index = usize::from(month).checked_sub(1)?;
+        self.month_names().split(' ').nth(index)
+    }
+
+    fn weekday_name(&self, weekday: Weekday) -> &'static str {
+        let index = weekday.num_days_from_monday() as usize;
+        self.weekday_names()
+            .split(' ')
+            .nth(index)
+            .unwrap_or_default()
+    }
+}
+
+/// A parsed `--format`, e.g. `[YEAR]/{[MODEL|slug]/}[MONTH]/`. Placeholders in square brackets are replaced by the
+/// values of the file and may be followed by filters separated by `|`. A segment in curly braces is left out when one of
+/// its placeholders is empty.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    Placeholder(Placeholder),
+    /// A segment that vanishes when one of its placeholders is empty
+    Optional(Vec<Part>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Placeholder {
+    value: Value,
+    filters: Vec<Filter>,
+}
+
+/// The values of a file placeholders stand for
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Weekday,
+    IsoWeek,
+    Quarter,
+    DayOfYear,
+    MonthName,
+    Stem,
+    Ext,
+    SrcDir,
+    /// An exif tag by its name, e.g. `Model` of `[EXIF:Model]` or `[MODEL]`
+    Exif(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Filter {
+    /// Replaces an empty value
+    Default(String),
+    Lower,
+    Upper,
+    /// Lower case letters and digits, everything else becomes a single `-`
+    Slug,
+    /// Keeps at most that many characters
+    Truncate(usize),
+}
+
+/// Parses a format, so mistakes are reported before any file is touched
+pub fn parse_template(value: &str) -> Result<Template, String> {
+    let mut parts = Vec::new();
+    let mut optional: Option<Vec<Part>> = None;
+    let mut text = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '[' => {
+                let rest = chars.as_str();
+                let (placeholder, after) = rest
+                    .split_once(']')
+                    .ok_or_else(|| format!("[{} is never closed", rest))?;
+                chars = after.chars();
+                let current = optional.as_mut().unwrap_or(&mut parts);
+                push_text(current, &mut text);
+                current.push(Part::Placeholder(parse_placeholder(placeholder)?));
+            }
+            ']' => return Err("] without [".to_string()),
+            '{' if optional.is_some() => {
+                return Err("Optional segments can not be nested".to_string())
+            }
+            '{' => {
+                push_text(&mut parts, &mut text);
+                optional = Some(Vec::new());
+            }
+            '}' => {
+                let mut segment = optional.take().ok_or("} without {")?;
+                push_text(&mut segment, &mut text);
+                if !segment
+                    .iter()
+                    .any(|part| matches!(part, Part::Placeholder(_)))
+                {
+                    return Err(format!(
+                        "The optional segment {{{}}} has no placeholder",
+                        segment_text(&segment)
+                    ));
+                }
+                parts.push(Part::Optional(segment));
+            }
+            c => text.push(c),
+        }
+    }
+    if optional.is_some() {
+        return Err("{ is never closed".to_string());
+    }
+    push_text(&mut parts, &mut text);
+    Ok(Template {
+        source: value.to_string(),
+        parts,
+    })
+}
+
+fn push_text(parts: &mut Vec<Part>, text: &mut String) {
+    if !text.is_empty() {
+        parts.push(Part::Text(std::mem::take(text)));
+    }
+}
+
+fn segment_text(parts: &[Part]) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => text.as_str(),
+            _ => "",
+        })
+        .collect()
+}
+
+/// Parses the inside of `[...]`, e.g. `EXIF:Model|lower|default:unknown`
+fn parse_placeholder(placeholder: &str) -> Result<Placeholder, String> {
+    let mut pieces = placeholder.split('|');
+    let name = pieces.next().unwrap_or_default();
+    let value = match name {
+        "YEAR" => Value::Year,
+        "MONTH" => Value::Month,
+        "DAY" => Value::Day,
+        "HOUR" => Value::Hour,
+        "MINUTE" => Value::Minute,
+        "SECOND" => Value::Second,
+        "WEEKDAY" => Value::Weekday,
+        "ISOWEEK" => Value::IsoWeek,
+        "QUARTER" => Value::Quarter,
+        "DOY" => Value::DayOfYear,
+        "MONTH_NAME" => Value::MonthName,
+        "STEM" => Value::Stem,
+        "EXT" => Value::Ext,
+        "SRCDIR" => Value::SrcDir,
+        "MAKE" => Value::Exif("Make".to_string()),
+        "MODEL" => Value::Exif("Model".to_string()),
+        name => match name.strip_prefix("EXIF:") {
+            Some(tag) if !tag.is_empty() => Value::Exif(tag.to_string()),
+            _ => return Err(format!("Unknown placeholder [{}]", name)),
+        },
+    };
+    let filters = pieces
+        .map(|filter| match filter.split_once(':') {
+            None if filter == "lower" => Ok(Filter::Lower),
+            None if filter == "upper" => Ok(Filter::Upper),
+            None if filter == "slug" => Ok(Filter::Slug),
+            Some(("default", default)) => Ok(Filter::Default(default.to_string())),
+            Some(("truncate", length)) => length
+                .parse()
+                .map(Filter::Truncate)
+                .map_err(|_| format!("truncate needs a length, e.g. truncate:20, not {}", length)),
+            _ => Err(format!(
+                "Unknown filter {} of [{}], use default:<value>, lower, upper, slug or truncate:<length>",
+                filter, placeholder
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Placeholder { value, filters })
+}
+
+/// What placeholders are replaced with
+struct Context<'a> {
+    image: &'a CopyImage,
+    /// The day of the image, unless it is a partial date
+    date: Option<NaiveDate>,
+    source_dir: &'a Path,
+    language: Language,
+}
+
+impl Template {
+    /// Replaces the placeholders with the values of the image. Values that are unknown for a partial date, like the
+    /// weekday of `2011/00/00`, are empty.
+    pub fn render(&self, image: &CopyImage, source_dir: &Path, language: Language) -> String {
+        let date_time = &image.date_time;
+        let context = Context {
+            image,
+            date: NaiveDate::from_ymd_opt(
+                i32::from(date_time.year),
+                u32::from(date_time.month),
+                u32::from(date_time.day),
+            ),
+            source_dir,
+            language,
+        };
+        render_parts(&self.parts, &context, false).unwrap_or_default()
+    }
+
+    /// The names of the exif tags of the template, e.g. `Model` of `[EXIF:Model]`
+    pub fn exif_tags(&self) -> Vec<&str> {
+        let parts = self.parts.iter().flat_map(|part| match part {
+            Part::Optional(segment) => segment.iter().collect(),
+            part => vec![part],
+        });
+        let mut tags = Vec::new();
+        for part in parts {
+            if let Part::Placeholder(Placeholder {
+                value: Value::Exif(tag),
+                ..
+            }) = part
+            {
+                if !tags.contains(&tag.as_str()) {
+                    tags.push(tag.as_str());
+                }
+            }
+        }
+        tags
+    }
+}
+
+/// The text of the parts, which is None if a placeholder is empty and the parts are optional
+fn render_parts(parts: &[Part], context: &Context, optional: bool) -> Option<String> {
+    let mut rendered = String::new();
+    for part in parts {
+        match part {
+            Part::Text(text) => rendered.push_str(text),
+            Part::Placeholder(placeholder) => {
+                let value = placeholder.render(context);
+                if optional && value.is_empty() {
+                    return None;
+                }
+                rendered.push_str(&value);
+            }
+            Part::Optional(segment) => {
+                rendered.push_str(&render_parts(segment, context, true).unwrap_or_default())
+            }
+        }
+    }
+    Some(rendered)
+}
+
+impl Placeholder {
+    fn render(&self, context: &Context) -> String {
+        let value = self.value.render(context);
+        self.filters
+            .iter()
+            .fold(value, |value, filter| filter.apply(value))
+    }
+}
+
+impl Value {
+    fn render(&self, context: &Context) -> String {
+        let date_time = &context.image.date_time;
+        let of_date =
+            |value: &dyn Fn(NaiveDate) -> String| context.date.map(value).unwrap_or_default();
+        let name = |name: Option<&OsStr>| {
+            name.map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        match self {
+            Value::Year => format!("{:04}", date_time.year),
+            Value::Month => format!("{:02}", date_time.month),
+            Value::Day => format!("{:02}", date_time.day),
+            Value::Hour => format!("{:02}", date_time.hour),
+            Value::Minute => format!("{:02}", date_time.minute),
+            Value::Second => format!("{:02}", date_time.second),
+            Value::Weekday => {
+                of_date(&|date| context.language.weekday_name(date.weekday()).to_string())
+            }
+            Value::IsoWeek => of_date(&|date| format!("{:02}", date.iso_week().week())),
+            Value::Quarter => match date_time.month {
+                0 => String::new(),
+                month => ((month - 1) / 3 + 1).to_string(),
+            },
+            Value::DayOfYear => of_date(&|date| format!("{:03}", date.ordinal())),
+            Value::MonthName => context
+                .language
+                .month_name(date_time.month)
+                .unwrap_or_default()
+                .to_string(),
+            Value::Stem => name(context.image.source.file_stem()),
+            Value::Ext => name(context.image.source.extension()),
+            Value::SrcDir => context
+                .image
+                .source
+                .parent()
+                .and_then(|parent| parent.strip_prefix(context.source_dir).ok())
+                .map(|dir| dir.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            Value::Exif(tag) => context
+                .image
+                .exif
+                .get(tag)
+                .map(|value| sanitize(value))
+                .unwrap_or_default(),
+        }
+    }
+}
+
+impl Filter {
+    fn apply(&self, value: String) -> String {
+        match self {
+            Filter::Default(default) if value.is_empty() => default.clone(),
+            Filter::Default(_) => value,
+            Filter::Lower => value.to_lowercase(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Slug => {
+                let slug: String = value
+                    .to_lowercase()
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                    .collect();
+                slug.split('-')
+                    .filter(|word| !word.is_empty())
+                    .collect::<Vec<_>>()
+                    .join("-")
+            }
+            Filter::Truncate(length) => value.chars().take(*length).collect(),
+        }
+    }
+}
+
+/// Shows the format as it was given, e.g. in verbose runs
+impl std::fmt::Debug for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.source)
+    }
+}
+
+/// Makes a value of the file usable as a single path component. Separators and characters not allowed on common file
+/// systems become `_`, leading and trailing spaces and dots are removed, so `..` cannot leave the dir.
+pub fn sanitize(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    value.trim_matches([' ', '.']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+
+    use crate::dick_sort::template::{parse_template, sanitize, Language};
+    use crate::dick_sort::{CopyImage, DateSource, SortedDayTime};
+
+    fn image(month: u8, day: u8) -> CopyImage {
+        CopyImage {
+            source: PathBuf::from("/src/2023/trip/IMG_0001.JPG"),
+            date_time: SortedDayTime {
+                year: 2023,
+                month,
+                day,
+                hour: 14,
+                minute: 30,
+                second: 12,
+                nanosecond: None,
+                offset: None,
+            },
+            date_source: DateSource::Exif,
+            date_tag: None,
+            exif: HashMap::from([
+                ("Model".to_string(), "Canon EOS 5D Mark IV".to_string()),
+                (
+                    "LensModel".to_string(),
+                    "EF24-70mm f/2.8L II USM".to_string(),
+                ),
+            ]),
+            sidecars: Vec::new(),
+        }
+    }
+
+    fn render(format: &str, image: &CopyImage, language: Language) -> String {
+        let template = parse_template(format).expect("should be a valid format");
+        template.render(image, Path::new("/src"), language)
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            render(
+                "[YEAR]/Q[QUARTER]/[YEAR]-[MONTH]-[DAY] [WEEKDAY]/",
+                &image(1, 5),
+                Language::En
+            ),
+            "2023/Q1/2023-01-05 Thu/"
+        );
+        assert_eq!(
+            render(
+                "[HOUR][MINUTE][SECOND] W[ISOWEEK] [DOY] [MONTH_NAME]/",
+                &image(3, 1),
+                Language::De
+            ),
+            "143012 W09 060 März/"
+        );
+        assert_eq!(
+            render("[SRCDIR]/[STEM].[EXT]/", &image(1, 5), Language::En),
+            "2023/trip/IMG_0001.JPG/"
+        );
+        // a partial date has no weekday, week or name of the month
+        assert_eq!(
+            render(
+                "[YEAR]/[QUARTER][MONTH_NAME][WEEKDAY][ISOWEEK][DOY]/",
+                &image(0, 0),
+                Language::En
+            ),
+            "2023//"
+        );
+    }
+
+    #[test]
+    fn exif_placeholders() {
+        let format = "[EXIF:Model]/{[EXIF:LensModel]/}[EXIF:ISOSpeed]/[MODEL]";
+        let template = parse_template(format).unwrap();
+        assert_eq!(template.exif_tags(), vec!["Model", "LensModel", "ISOSpeed"]);
+        assert_eq!(
+            render(format, &image(1, 5), Language::En),
+            "Canon EOS 5D Mark IV/EF24-70mm f_2.8L II USM//Canon EOS 5D Mark IV"
+        );
+        assert_eq!(sanitize(" ..a<b>:c\\d|e?*\"\u{7} "), "a_b__c_d_e____");
+        assert_eq!(sanitize(".."), "");
+    }
+
+    #[test]
+    fn filters_and_defaults() {
+        let image = image(1, 5);
+        let render = |format| render(format, &image, Language::En);
+        assert_eq!(render("[MODEL|slug]/"), "canon-eos-5d-mark-iv/");
+        assert_eq!(render("[MODEL|upper|truncate:5]/"), "CANON/");
+        assert_eq!(
+            render("[EXIF:LensModel|lower]/"),
+            "ef24-70mm f_2.8l ii usm/"
+        );
+        assert_eq!(render("[MAKE|default:unknown]/"), "unknown/");
+        assert_eq!(render("[MODEL|default:unknown]/"), "Canon EOS 5D Mark IV/");
+        // the default may be changed by later filters
+        assert_eq!(render("[MAKE|default:No Camera|slug]/"), "no-camera/");
+    }
+
+    #[test]
+    fn optional_segments() {
+        let image = image(1, 5);
+        let render = |format| render(format, &image, Language::En);
+        assert_eq!(render("[YEAR]/{[MAKE] }[MONTH]/"), "2023/01/");
+        assert_eq!(
+            render("[YEAR]/{[MODEL|slug]/}[MONTH]/"),
+            "2023/canon-eos-5d-mark-iv/01/"
+        );
+        assert_eq!(render("[YEAR]/{[MAKE|default:any]/}"), "2023/any/");
+        assert_eq!(render("[YEAR]/{[MODEL]-[MAKE]/}"), "2023/");
+    }
+
+    #[test]
+    fn invalid_formats() {
+        for format in [
+            "[YEAR",
+            "YEAR]",
+            "[YEARS]",
+            "[]",
+            "[EXIF:]",
+            "[YEAR|title]",
+            "[YEAR|truncate:a]",
+            "[YEAR|truncate]",
+            "{[YEAR]",
+            "[YEAR]}",
+            "{{[YEAR]}}",
+            "{static}",
+        ] {
+            assert!(
+                parse_template(format).is_err(),
+                "{} should be invalid",
+                format
+            );
+        }
+        assert!(parse_template("").is_ok());
+    }
+}
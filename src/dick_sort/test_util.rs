@@ -0,0 +1,10 @@
+use std::fs;
+use std::path::PathBuf;
+
+/// An empty dir of the test in the temp dir, unique to the name and process
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dicksort_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("should create test dir");
+    dir
+}
@@ -0,0 +1,143 @@
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+use crate::dick_sort::{hash, ReadError};
+
+/// How a move ended
+#[derive(Debug)]
+pub enum Moved {
+    /// The file of the size is at the target and the source is gone
+    Moved(u64),
+    /// The file of the size is at the target, but the source could not be removed, so it was copied
+    Copied(u64, ReadError),
+}
+
+/// Moves the file. Falls back to a verified copy if source and target are on different devices.
+pub fn move_file(source: &Path, target: &Path) -> Result<Moved, ReadError> {
+    match fs::rename(source, target) {
+        Ok(()) => fs::metadata(target)
+            .map(|meta| Moved::Moved(meta.len()))
+            .map_err(|err| ReadError {
+                msg: err.to_string(),
+            }),
+        Err(err) if err.kind() == io::ErrorKind::CrossesDevices => {
+            move_across_devices(source, target)
+        }
+        Err(err) => Err(ReadError {
+            msg: format!("Could not move {:?} to {:?}: {}", source, target, err),
+        }),
+    }
+}
+
+/// Copies to a temporary file next to the target, syncs and verifies it, renames it into place and only then
+/// removes the source. If anything fails before the rename, the source is untouched and the temporary file removed.
+fn move_across_devices(source: &Path, target: &Path) -> Result<Moved, ReadError> {
+    let temp = temp_path(target);
+    // a leftover from an interrupted run
+    let _ = fs::remove_file(&temp);
+    let size = match copy_verified(source, &temp) {
+        Ok(size) => size,
+        Err(err) => {
+            let _ = fs::remove_file(&temp);
+            return Err(err);
+        }
+    };
+
+    if let Err(err) = fs::rename(&temp, target) {
+        let _ = fs::remove_file(&temp);
+        return Err(ReadError {
+            msg: format!("Could not rename {:?} to {:?}: {}", temp, target, err),
+        });
+    }
+
+    Ok(match fs::remove_file(source) {
+        Ok(()) => Moved::Moved(size),
+        Err(err) => Moved::Copied(
+            size,
+            ReadError {
+                msg: format!("Could not remove {:?}: {}", source, err),
+            },
+        ),
+    })
+}
+
+fn copy_verified(source: &Path, temp: &Path) -> Result<u64, ReadError> {
+    let mut source_file = File::open(source).map_err(|err| ReadError {
+        msg: format!("Could not open {:?}: {}", source, err),
+    })?;
+    let mut temp_file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(temp)
+        .map_err(|err| ReadError {
+            msg: format!("Could not create {:?}: {}", temp, err),
+        })?;
+
+    let size = io::copy(&mut source_file, &mut temp_file).map_err(|err| ReadError {
+        msg: format!("Could not copy {:?} to {:?}: {}", source, temp, err),
+    })?;
+    // keep what a rename on one device keeps, the modification time is what --on-conflict keep-newer compares
+    let keep_metadata = || -> io::Result<()> {
+        let metadata = source_file.metadata()?;
+        temp_file.set_permissions(metadata.permissions())?;
+        temp_file.set_modified(metadata.modified()?)
+    };
+    keep_metadata().map_err(|err| ReadError {
+        msg: format!(
+            "Could not copy the metadata of {:?} to {:?}: {}",
+            source, temp, err
+        ),
+    })?;
+    temp_file.sync_all().map_err(|err| ReadError {
+        msg: format!("Could not sync {:?}: {}", temp, err),
+    })?;
+
+    if hash::hash_file(source)? != hash::hash_file(temp)? {
+        return Err(ReadError {
+            msg: format!("Copy of {:?} does not match the source", source),
+        });
+    }
+    Ok(size)
+}
+
+/// A hidden file in the target's directory, so the final rename stays on one device
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.dicksort-tmp", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::{Duration, SystemTime};
+
+    use crate::dick_sort::test_util::test_dir;
+    use crate::dick_sort::transfer::{move_across_devices, Moved};
+
+    #[test]
+    fn verified_move_removes_source() {
+        let dir = test_dir("transfer");
+        let source = dir.join("a.jpg");
+        let target = dir.join("b.jpg");
+        fs::write(&source, b"content").unwrap();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&source)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let moved = move_across_devices(&source, &target).unwrap();
+        assert!(matches!(moved, Moved::Moved(7)));
+        assert!(!source.exists());
+        assert_eq!(fs::metadata(&target).unwrap().modified().unwrap(), modified);
+        assert_eq!(fs::read(&target).unwrap(), b"content");
+        assert!(!dir.join(".b.jpg.dicksort-tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
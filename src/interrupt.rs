@@ -0,0 +1,23 @@
+use anyhow::Context;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Installs a Ctrl-C handler that asks the run to stop after the files in flight.
+/// A second Ctrl-C terminates immediately.
+pub fn install() -> anyhow::Result<()> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
+        }
+        eprintln!(
+            "\nStopping after the files in progress. Press Ctrl-C again to stop immediately."
+        );
+    })
+    .context("Could not install Ctrl-C handler")
+}
+
+/// Whether the run should stop as soon as possible
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -1,16 +1,27 @@
 use anyhow::bail;
 use std::path::PathBuf;
 
+use chrono::{NaiveDate, NaiveDateTime};
+use regex::Regex;
+
+use crate::dick_sort::{
+    parse_camera_shifts, parse_filename_pattern, parse_folder_pattern, parse_shift, parse_template,
+    parse_timezone, CameraShifts, DateSource, DateStrategy, DateTag, Language, OnConflict, Shift,
+    Template, Timezone,
+};
 use crate::shell::{PrintLevel, Shell};
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches, Parser};
 
 mod dick_sort;
+mod interrupt;
 mod progress;
 mod shell;
 
 /// Sorts pics from one directory into other ones
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
+// `undo` is only taken for the subcommand in place of the dirs, `dicksort -r undo <destination>` sorts a dir named undo
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Cli {
     /// The path from where the pics will be read
     #[clap(parse(from_os_str))]
@@ -40,17 +51,189 @@ pub struct Cli {
     #[clap(short, long, value_parser, default_value_t = false)]
     clean: bool,
 
-    /// Log progress of scanning
+    /// Log progress of scanning. What happens to each file is printed above the progress.
     #[clap(short, long, value_parser, default_value_t = false)]
     progress: bool,
 
-    /// Format of the path under destination_dir
-    #[clap(short, long, value_parser, default_value_t = String::from("[YEAR]/[MONTH]/[DAY]/"))]
-    format: String,
+    /// Format of the path under destination_dir, which is followed by the file name. Placeholders: [YEAR], [MONTH],
+    /// [DAY], [HOUR], [MINUTE], [SECOND], [WEEKDAY], [ISOWEEK], [QUARTER], [DOY] (day of the year), [MONTH_NAME],
+    /// [STEM], [EXT], [SRCDIR] (the dir of the file relative to source_dir), [MAKE], [MODEL] and any exif tag, e.g.
+    /// [EXIF:LensModel]. Filters follow a `|`: default:<value>, lower, upper, slug and truncate:<length>, e.g.
+    /// [MODEL|slug|default:unknown]. A segment in curly braces is left out if one of its placeholders is empty.
+    #[clap(short, long, value_parser = parse_template, default_value = "[YEAR]/[MONTH]/[DAY]/")]
+    format: Template,
+
+    /// Language of [MONTH_NAME] and [WEEKDAY]. Defaults to the language of LC_ALL, LC_TIME or LANG, otherwise English.
+    #[clap(long, arg_enum, value_parser, default_value_t = Language::from_env())]
+    language: Language,
+
+    /// What to do if a file with the same name already exists at the destination
+    #[clap(long, arg_enum, value_parser, default_value_t = OnConflict::Rename)]
+    on_conflict: OnConflict,
+
+    /// Number of files read and copied or moved in parallel
+    #[clap(
+        short,
+        long,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..),
+        default_value_t = 1
+    )]
+    jobs: usize,
+
+    /// Continue an interrupted run, skipping the files it already handled
+    #[clap(long, value_parser, default_value_t = false)]
+    resume: bool,
+
+    /// Recognize media files by their extension only instead of their content. Faster, but misses renamed or
+    /// extensionless files
+    #[clap(long, value_parser, default_value_t = false)]
+    by_extension: bool,
+
+    /// Where dates are read from, by precedence. The date is picked by the date strategy from the first source that has
+    /// a plausible one, sources left out are ignored
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        use_value_delimiter = true,
+        default_values_t = [
+            DateSource::Sidecar,
+            DateSource::Xmp,
+            DateSource::Exif,
+            DateSource::Filename,
+            DateSource::Folder
+        ]
+    )]
+    date_precedence: Vec<DateSource>,
+
+    /// How the date is picked if a source has several, e.g. exif with an original and a modification date
+    #[clap(long, arg_enum, value_parser, default_value_t = DateStrategy::PreferOriginal)]
+    date_strategy: DateStrategy,
+
+    /// Tags by priority for the date strategies priority and prefer-original. Tags left out are ignored by them.
+    #[clap(
+        long,
+        arg_enum,
+        value_parser,
+        use_value_delimiter = true,
+        default_values_t = [
+            DateTag::Original,
+            DateTag::Digitized,
+            DateTag::Modified,
+            DateTag::Gps,
+            DateTag::Container
+        ]
+    )]
+    tag_priority: Vec<DateTag>,
+
+    /// Time zone the files are sorted in: `local` keeps the local time of every file, `system` converts to the zone of
+    /// this machine, `UTC` or an offset like `+02:00` to that offset. Files without a recorded offset count as
+    /// taken in the zone of this machine.
+    #[clap(long, value_parser = parse_timezone, default_value = "local")]
+    timezone: Timezone,
+
+    /// Corrects the clock of the camera by moving the dates of exif, embedded XMP and videos, but not the GPS time,
+    /// before they are checked and sorted, e.g. `+1y-1h`, of years (y), months (mo), weeks (w), days (d), hours (h), minutes (m) and seconds (s).
+    /// The files are not changed.
+    #[clap(long, value_parser = parse_shift, allow_hyphen_values = true)]
+    shift: Option<Shift>,
+
+    /// File with a shift per camera, one `<camera> = <shift>` per line, e.g. `Canon EOS 5D Mark IV = +1y-1h`. The
+    /// camera is the body serial number, the model or make and model of the exif. Wins over `--shift`.
+    #[clap(long, value_parser = parse_camera_shifts)]
+    camera_shifts: Option<CameraShifts>,
+
+    /// Dates before this day are rejected as implausible, e.g. `1990-01-01`
+    #[clap(long, value_parser, default_value = "1990-01-01")]
+    not_before: NaiveDate,
+
+    /// Dates after this day are rejected as implausible. Defaults to tomorrow.
+    #[clap(long, value_parser)]
+    not_after: Option<NaiveDate>,
+
+    /// Dates cameras reset their clock to, which are rejected as implausible
+    #[clap(
+        long,
+        value_parser,
+        use_value_delimiter = true,
+        default_values = &[
+            "2000-01-01T00:00:00",
+            "2001-01-01T00:00:00",
+            "2002-01-01T00:00:00",
+            "2004-01-01T00:00:00",
+            "2005-01-01T00:00:00",
+            "2010-01-01T00:00:00"
+        ]
+    )]
+    blocked_dates: Vec<NaiveDateTime>,
+
+    /// Sub dir of destination_dir for media files without a plausible date, which are left alone otherwise
+    #[clap(long, value_parser)]
+    undated: Option<PathBuf>,
+
+    /// Keep the path relative to source_dir of the files in the undated dir
+    #[clap(long, value_parser, default_value_t = false, requires = "undated")]
+    undated_keep_paths: bool,
+
+    /// Regex for dates in file names with the named groups year, month and day and optionally hour, minute and
+    /// second, e.g. `(?P<day>\d{2})\.(?P<month>\d{2})\.(?P<year>\d{4})`. Tried before the built-in patterns.
+    #[clap(long = "filename-pattern", value_parser = parse_filename_pattern)]
+    filename_patterns: Vec<Regex>,
+
+    /// Regex for dates in the folders under source_dir, matched against the path of the folder relative to it, e.g.
+    /// `2011/07 Italy`. Needs the named group year and optionally month and day. The last match wins.
+    #[clap(long, value_parser = parse_folder_pattern)]
+    folder_pattern: Option<Regex>,
+
+    /// Date files without any other date by the earlier of their modification and birth time. These are often reset
+    /// by copying tools, so such files are counted in the summary.
+    #[clap(long, value_parser, default_value_t = false)]
+    fallback_mtime: bool,
+
+    /// Extensions of sidecar files, which are copied or moved together with the file of the same name. Disable with
+    /// `--sidecars ''`
+    #[clap(
+        long,
+        value_parser,
+        use_value_delimiter = true,
+        default_values = &["xmp", "aae", "thm", "pp3"]
+    )]
+    sidecars: Vec<String>,
+}
+
+/// Reverts a previous run using the journal it wrote into its destination_dir
+#[derive(Parser, Debug)]
+#[clap(name = "undo")]
+pub struct UndoCli {
+    /// The journal file of the run
+    #[clap(parse(from_os_str))]
+    journal: PathBuf,
+
+    /// If set, verbose output is created
+    #[clap(short, long, value_parser, default_value_t = false)]
+    verbose: bool,
+
+    /// If set, nothing is reverted, only described
+    #[clap(short, long, value_parser, default_value_t = false)]
+    dry_run: bool,
 }
 
 fn main() -> anyhow::Result<()> {
-    let args: Cli = Cli::try_parse()?;
+    // the subcommand is added here, as Cli can only be built from the matches without it
+    let matches = Cli::command()
+        .subcommand(UndoCli::command())
+        .try_get_matches()?;
+    if let Some(("undo", matches)) = matches.subcommand() {
+        let args = UndoCli::from_arg_matches(matches)?;
+        let print_level = if args.verbose {
+            PrintLevel::Verbose
+        } else {
+            PrintLevel::Normal
+        };
+        return dick_sort::undo(args, &mut Shell::new(print_level));
+    }
+
+    let args = Cli::from_arg_matches(&matches)?;
 
     let print_level = if args.verbose {
         PrintLevel::Verbose
@@ -67,6 +250,10 @@ fn main() -> anyhow::Result<()> {
     if !args.source_dir.exists() {
         bail!("source_dir must exist");
     }
+    if args.undated.as_ref().is_some_and(|dir| dir.is_absolute()) {
+        bail!("undated must be a dir relative to destination_dir");
+    }
 
+    interrupt::install()?;
     dick_sort::sort(args, &mut shell)
 }
@@ -1,14 +1,30 @@
 use anyhow::Context;
 use std::io::{Stdout, Write};
 use std::path::Path;
-use termion::raw::RawTerminal;
+use std::sync::{Mutex, MutexGuard};
+
+/// The progress report shared by the scanner and the processing of the files
+pub type SharedProgressReport = Mutex<Box<dyn ProgressReport + Send>>;
+
+pub fn lock(progress: &SharedProgressReport) -> MutexGuard<'_, Box<dyn ProgressReport + Send>> {
+    progress
+        .lock()
+        .expect("The progress report should not be poisoned")
+}
 
 pub trait ProgressReport {
     fn set_remaining_dirs(&mut self, remaining_dirs: usize) -> anyhow::Result<()>;
     fn set_collected_files(&mut self, collected_files: usize) -> anyhow::Result<()>;
     fn set_current_dir(&mut self, dir: &Path) -> anyhow::Result<()>;
+    fn set_dir_progress(&mut self, read_files: usize, total_files: usize) -> anyhow::Result<()>;
     fn set_current_file(&mut self, file: &Path) -> anyhow::Result<()>;
     fn flush(&mut self) -> anyhow::Result<()>;
+    /// Prints a line to stdout without disturbing the report
+    fn println(&mut self, line: &str);
+    /// Prints a line to stderr without disturbing the report
+    fn eprintln(&mut self, line: &str);
+    /// Leaves the report as it is and moves on below it
+    fn finish(&mut self) -> anyhow::Result<()>;
 }
 
 pub struct NoopProgressReport;
@@ -26,6 +42,10 @@ impl ProgressReport for NoopProgressReport {
         Ok(())
     }
 
+    fn set_dir_progress(&mut self, _read_files: usize, _total_files: usize) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     fn set_current_file(&mut self, _file: &Path) -> anyhow::Result<()> {
         Ok(())
     }
@@ -33,73 +53,102 @@ impl ProgressReport for NoopProgressReport {
     fn flush(&mut self) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn println(&mut self, line: &str) {
+        println!("{}", line);
+    }
+
+    fn eprintln(&mut self, line: &str) {
+        eprintln!("{}", line);
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
 
+/// How many lines the terminal report takes
+const LINES: usize = 5;
+
+/// Draws the report as the last lines of the output, relative to the cursor, which stays at the start of the first
+/// one. Lines printed meanwhile go above the report, which is drawn again below them.
 pub struct TerminalProgressReport {
-    term: RawTerminal<Stdout>,
-    base_line: u16,
+    term: Stdout,
+    lines: [String; LINES],
+    width: usize,
 }
 
 impl TerminalProgressReport {
     pub fn new() -> anyhow::Result<Self> {
-        use termion::raw::IntoRawMode as _;
-        let mut term = std::io::stdout().into_raw_mode()?;
+        let width = termion::terminal_size()
+            .map(|(width, _)| usize::from(width))
+            .unwrap_or(80);
+        let mut report = Self {
+            term: std::io::stdout(),
+            lines: Default::default(),
+            width,
+        };
+        report.draw()?;
+        Ok(report)
+    }
 
-        // Create empty lines for output
-        for _ in 0..4 {
-            writeln!(term)?;
+    fn set_line(&mut self, index: usize, line: String) -> anyhow::Result<()> {
+        // a wrapped line would move the lines below out of place
+        let line: String = line.chars().take(self.width.saturating_sub(1)).collect();
+        if index > 0 {
+            write!(self.term, "{}", termion::cursor::Down(index as u16))?;
         }
+        write!(self.term, "\r{}{}\r", termion::clear::CurrentLine, line)?;
+        if index > 0 {
+            write!(self.term, "{}", termion::cursor::Up(index as u16))?;
+        }
+        self.lines[index] = line;
+        Ok(())
+    }
 
-        use termion::cursor::DetectCursorPos as _;
-        let (_, base_line) = term.cursor_pos()?;
+    fn draw(&mut self) -> anyhow::Result<()> {
+        write!(
+            self.term,
+            "\r{}{}\r{}",
+            termion::clear::AfterCursor,
+            self.lines.join("\n"),
+            termion::cursor::Up(LINES as u16 - 1)
+        )?;
+        Ok(())
+    }
 
-        Ok(Self { term, base_line })
+    /// Clears the report, lets the caller print in its place and draws it again below
+    fn print_above(&mut self, print: impl FnOnce(&mut Stdout)) -> anyhow::Result<()> {
+        write!(self.term, "\r{}", termion::clear::AfterCursor)?;
+        self.term.flush()?;
+        print(&mut self.term);
+        self.draw()?;
+        self.flush()
     }
 }
 
 impl ProgressReport for TerminalProgressReport {
     fn set_remaining_dirs(&mut self, remaining_dirs: usize) -> anyhow::Result<()> {
-        write!(
-            self.term,
-            "{}{}Remaining directories: {}",
-            termion::cursor::Goto(1, self.base_line - 3),
-            termion::clear::CurrentLine,
-            remaining_dirs
-        )?;
-        Ok(())
+        self.set_line(0, format!("Remaining directories: {}", remaining_dirs))
     }
 
     fn set_collected_files(&mut self, collected_files: usize) -> anyhow::Result<()> {
-        write!(
-            self.term,
-            "{}{}      Collected files: {}",
-            termion::cursor::Goto(1, self.base_line - 2),
-            termion::clear::CurrentLine,
-            collected_files
-        )?;
-        Ok(())
+        self.set_line(1, format!("      Collected files: {}", collected_files))
     }
 
     fn set_current_dir(&mut self, dir: &Path) -> anyhow::Result<()> {
-        write!(
-            self.term,
-            "{}{}       processing dir: {:?}",
-            termion::cursor::Goto(1, self.base_line - 1),
-            termion::clear::CurrentLine,
-            dir
-        )?;
-        Ok(())
+        self.set_line(2, format!("       processing dir: {:?}", dir))
+    }
+
+    fn set_dir_progress(&mut self, read_files: usize, total_files: usize) -> anyhow::Result<()> {
+        self.set_line(
+            3,
+            format!("     files in dir read: {}/{}", read_files, total_files),
+        )
     }
 
     fn set_current_file(&mut self, file: &Path) -> anyhow::Result<()> {
-        write!(
-            self.term,
-            "{}{}      processing file: {:?}",
-            termion::cursor::Goto(1, self.base_line),
-            termion::clear::CurrentLine,
-            file
-        )?;
-        Ok(())
+        self.set_line(4, format!("      processing file: {:?}", file))
     }
 
     fn flush(&mut self) -> anyhow::Result<()> {
@@ -108,4 +157,21 @@ impl ProgressReport for TerminalProgressReport {
             .context("We should be able to flush output ")?;
         Ok(())
     }
+
+    fn println(&mut self, line: &str) {
+        self.print_above(|term| {
+            writeln!(term, "{}", line).expect("We should be able to print");
+        })
+        .expect("We should be able to print");
+    }
+
+    fn eprintln(&mut self, line: &str) {
+        self.print_above(|_| eprintln!("{}", line))
+            .expect("We should be able to print");
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        writeln!(self.term, "{}", termion::cursor::Down(LINES as u16 - 1))?;
+        self.flush()
+    }
 }
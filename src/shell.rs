@@ -4,7 +4,7 @@ pub enum PrintLevel {
     Normal,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Shell {
     level: Option<PrintLevel>,
 }